@@ -0,0 +1,144 @@
+/*
+    ChessLib, a UCI chess engine
+    Copyright (C) 2023 Sam Price
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Compare against an earlier run with
+//   cargo bench -- --save-baseline before
+//   cargo bench -- --baseline before
+
+use std::hint::black_box;
+use std::str::FromStr;
+use std::sync::Once;
+
+use chess_lib::evaluate::static_evaluate;
+use chess_lib::prelude::*;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+const MIDDLEGAME: &str = "r1bq1rk1/pp2bppp/2n1pn2/3p4/2PP4/2N1PN2/PP2BPPP/R2QKB1R w KQ - 0 8";
+
+struct PerftCase {
+    name: &'static str,
+    setup: fn() -> Position,
+    depth: usize,
+    nodes: usize,
+}
+
+// Known node counts, a faster but wrong movegen must not look like a win
+const PERFT: [PerftCase; 4] = [
+    PerftCase { name: "startpos", setup: Position::startpos, depth: 3, nodes: 8_902 },
+    PerftCase { name: "startpos", setup: Position::startpos, depth: 4, nodes: 197_281 },
+    PerftCase { name: "kiwipete", setup: Position::kiwipete, depth: 2, nodes: 2_039 },
+    PerftCase { name: "kiwipete", setup: Position::kiwipete, depth: 3, nodes: 97_862 },
+];
+
+fn perft(c: &mut Criterion) {
+    setup();
+    let mut group = c.benchmark_group("perft");
+    group.sample_size(20);
+    for PerftCase { name, setup, depth, nodes } in PERFT {
+        let mut pos = setup();
+        assert_eq!(pos.perft::<false>(depth), nodes, "{name} perft {depth}");
+
+        group.throughput(Throughput::Elements(nodes as u64));
+        group.bench_with_input(BenchmarkId::new(name, depth), &depth, |b, &depth| {
+            b.iter(|| pos.perft::<false>(black_box(depth)))
+        });
+    }
+    group.finish();
+}
+
+fn movegen(c: &mut Criterion) {
+    setup();
+    let pos = Position::kiwipete();
+    c.bench_function("generate_legal kiwipete", |b| {
+        b.iter(|| {
+            let mut list = MoveList::new();
+            generate_legal::<false>(black_box(&pos), &mut list);
+            list.len()
+        })
+    });
+}
+
+fn make_unmake(c: &mut Criterion) {
+    setup();
+    let mut pos = Position::kiwipete();
+    let moves = pos.legal_moves();
+
+    let mut group = c.benchmark_group("do_move");
+    group.throughput(Throughput::Elements(moves.len() as u64));
+    group.bench_function("kiwipete all moves", |b| {
+        b.iter(|| {
+            for i in 0..moves.len() {
+                let m = moves.get(i);
+                pos.do_move(black_box(m));
+                pos.undo_move(m);
+            }
+        })
+    });
+    group.finish();
+}
+
+// gives_check against the do_move + in_check it saves the search from
+fn gives_check(c: &mut Criterion) {
+    setup();
+    let mut pos = Position::from_str(MIDDLEGAME).unwrap();
+    let moves = pos.legal_moves();
+
+    let mut group = c.benchmark_group("gives_check");
+    group.throughput(Throughput::Elements(moves.len() as u64));
+    group.bench_function("gives_check", |b| {
+        b.iter(|| {
+            (0..moves.len())
+                .filter(|&i| pos.gives_check(black_box(moves.get(i))))
+                .count()
+        })
+    });
+    group.bench_function("do_move in_check", |b| {
+        b.iter(|| {
+            let mut checks = 0;
+            for i in 0..moves.len() {
+                let m = moves.get(i);
+                pos.do_move(black_box(m));
+                checks += pos.in_check() as usize;
+                pos.undo_move(m);
+            }
+            checks
+        })
+    });
+    group.finish();
+}
+
+fn evaluate(c: &mut Criterion) {
+    setup();
+    let mut group = c.benchmark_group("static_evaluate");
+    for (name, pos) in [
+        ("startpos", Position::startpos()),
+        ("kiwipete", Position::kiwipete()),
+        ("middlegame", Position::from_str(MIDDLEGAME).unwrap()),
+    ] {
+        group.bench_function(name, |b| b.iter(|| static_evaluate(black_box(&pos))));
+    }
+    group.finish();
+}
+
+fn setup() {
+    static INIT: Once = Once::new();
+    INIT.call_once(chess_lib::initialize);
+}
+
+criterion_group!(benches, perft, movegen, make_unmake, gives_check, evaluate);
+criterion_main!(benches);
@@ -0,0 +1,38 @@
+/*
+    ChessLib, a UCI chess engine
+    Copyright (C) 2023 Sam Price
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use crate::piece::Piece;
+use crate::square::Square;
+
+// Told about every piece that appears or disappears in do_move/undo_move,
+// so an incremental evaluator (NNUE accumulators) never has to diff boards.
+// Captures are reported as: captured piece removed, mover removed, mover
+// (or the promoted piece) added. Castling lifts the king and then the rook
+// before putting them down in the same order.
+pub trait AccumulatorListener {
+    fn piece_added(&mut self, square: Square, piece: Piece);
+    fn piece_removed(&mut self, square: Square, piece: Piece);
+}
+
+// No listener, compiles away entirely
+impl AccumulatorListener for () {
+    #[inline(always)]
+    fn piece_added(&mut self, _square: Square, _piece: Piece) {}
+    #[inline(always)]
+    fn piece_removed(&mut self, _square: Square, _piece: Piece) {}
+}
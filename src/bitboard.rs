@@ -44,7 +44,7 @@ impl Bitboard {
 
     #[inline(always)]
     pub const fn more_than_one(self) -> bool {
-        (self.0 - 1) & self.0 > 0
+        self.0.wrapping_sub(1) & self.0 > 0
     }
 
     #[inline(always)]
@@ -61,6 +61,36 @@ impl Bitboard {
         self.0.count_ones()
     }
 
+    #[inline(always)]
+    pub const fn parity(self) -> bool {
+        self.popcnt() & 1 == 1
+    }
+
+    // Every subset of the set bits, starting from the empty board
+    pub fn subsets(self) -> Subsets {
+        Subsets {
+            mask: self,
+            next: Some(Self::ZERO),
+        }
+    }
+
+    pub fn from_squares(squares: &[Square]) -> Self {
+        squares.iter().fold(Self::ZERO, |bb, &s| bb | s)
+    }
+
+    #[inline(always)]
+    pub const fn contains(self, square: Square) -> bool {
+        self.0 & (1 << square.inner()) != 0
+    }
+    #[inline(always)]
+    pub const fn with(self, square: Square) -> Self {
+        Self(self.0 | (1 << square.inner()))
+    }
+    #[inline(always)]
+    pub const fn without(self, square: Square) -> Self {
+        Self(self.0 & !(1 << square.inner()))
+    }
+
     #[inline]
     pub fn and_not<T: Into<Self>>(self, rhs: T) -> Self {
         self & !rhs.into()
@@ -98,11 +128,43 @@ impl Bitboard {
         }
     }
 
+    // The lowest set square, the board must not be empty
     #[inline(always)]
     pub const fn get_square(self) -> Square {
+        debug_assert!(self.nonzero(), "get_square on an empty bitboard");
         unsafe { Square::new(self.0.trailing_zeros() as u8) }
     }
 
+    #[inline]
+    pub const fn get_square_checked(self) -> Option<Square> {
+        if self.zero() {
+            None
+        } else {
+            Some(self.get_square())
+        }
+    }
+
+    // Lowest and highest set squares, None rather than Square::NULL when empty
+    #[inline]
+    pub const fn first_square(self) -> Option<Square> {
+        self.get_square_checked()
+    }
+    #[inline]
+    pub const fn last_square(self) -> Option<Square> {
+        if self.zero() {
+            None
+        } else {
+            Some(unsafe { Square::new(63 - self.0.leading_zeros() as u8) })
+        }
+    }
+
+    // The set squares in ascending order, for callers wanting a slice
+    pub fn to_squares(self) -> Vec<Square> {
+        let mut squares = Vec::with_capacity(self.popcnt() as usize);
+        self.map_by_square(|s| squares.push(s));
+        squares
+    }
+
     #[inline(always)]
     pub fn pop_square(&mut self) -> Square {
         let s = self.get_square();
@@ -114,11 +176,135 @@ impl Bitboard {
     pub const fn relative(self, color: Color) -> Self {
         match color {
             Color::White => self,
-            Color::Black => Self(self.0.swap_bytes()),
+            Color::Black => self.flip_vertical(),
+        }
+    }
+
+    // Board symmetries, see the chessprogramming wiki's "Flipping Mirroring
+    // and Rotating" for the masks
+
+    // Rank 1 <-> rank 8
+    #[inline(always)]
+    pub const fn flip_vertical(self) -> Self {
+        Self(self.0.swap_bytes())
+    }
+
+    // File a <-> file h
+    #[inline(always)]
+    pub const fn flip_horizontal(self) -> Self {
+        const K1: u64 = 0x5555555555555555;
+        const K2: u64 = 0x3333333333333333;
+        const K4: u64 = 0x0f0f0f0f0f0f0f0f;
+        let mut x = self.0;
+        x = ((x >> 1) & K1) | ((x & K1) << 1);
+        x = ((x >> 2) & K2) | ((x & K2) << 2);
+        x = ((x >> 4) & K4) | ((x & K4) << 4);
+        Self(x)
+    }
+
+    // Mirrored on the a1-h8 diagonal
+    #[inline(always)]
+    pub const fn flip_diagonal(self) -> Self {
+        const K1: u64 = 0x5500550055005500;
+        const K2: u64 = 0x3333000033330000;
+        const K4: u64 = 0x0f0f0f0f00000000;
+        let mut x = self.0;
+        let mut t = K4 & (x ^ (x << 28));
+        x ^= t ^ (t >> 28);
+        t = K2 & (x ^ (x << 14));
+        x ^= t ^ (t >> 14);
+        t = K1 & (x ^ (x << 7));
+        x ^= t ^ (t >> 7);
+        Self(x)
+    }
+
+    // Mirrored on the a8-h1 diagonal
+    #[inline(always)]
+    pub const fn flip_anti_diagonal(self) -> Self {
+        const K1: u64 = 0xaa00aa00aa00aa00;
+        const K2: u64 = 0xcccc0000cccc0000;
+        const K4: u64 = 0xf0f0f0f00f0f0f0f;
+        let mut x = self.0;
+        let mut t = x ^ (x << 36);
+        x ^= K4 & (t ^ (x >> 36));
+        t = K2 & (x ^ (x << 18));
+        x ^= t ^ (t >> 18);
+        t = K1 & (x ^ (x << 9));
+        x ^= t ^ (t >> 9);
+        Self(x)
+    }
+
+    #[inline(always)]
+    pub const fn rotate_180(self) -> Self {
+        Self(self.0.reverse_bits())
+    }
+
+    // Single pawn pushes for `color` that land on `empty`
+    #[inline(always)]
+    pub const fn pawn_pushes(self, color: Color, empty: Self) -> Self {
+        match color {
+            Color::White => Self((self.0 << 8) & empty.0),
+            Color::Black => Self((self.0 >> 8) & empty.0),
+        }
+    }
+
+    // Every square attacked by this set of pawns
+    #[inline(always)]
+    pub const fn pawn_attacks_spread(self, color: Color) -> Self {
+        let not_a = !FILE_BB[0].0;
+        let not_h = !FILE_BB[7].0;
+        match color {
+            Color::White => Self(((self.0 << 9) & not_a) | ((self.0 << 7) & not_h)),
+            Color::Black => Self(((self.0 >> 7) & not_a) | ((self.0 >> 9) & not_h)),
         }
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct BitboardIter(Bitboard);
+
+impl Iterator for BitboardIter {
+    type Item = Square;
+    fn next(&mut self) -> Option<Square> {
+        if self.0.zero() {
+            None
+        } else {
+            Some(self.0.pop_square())
+        }
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = self.0.popcnt() as usize;
+        (n, Some(n))
+    }
+}
+
+impl ExactSizeIterator for BitboardIter {}
+
+impl IntoIterator for Bitboard {
+    type Item = Square;
+    type IntoIter = BitboardIter;
+    fn into_iter(self) -> BitboardIter {
+        BitboardIter(self)
+    }
+}
+
+// Carry-Rippler enumeration of the subsets of `mask`
+#[derive(Debug, Clone)]
+pub struct Subsets {
+    mask: Bitboard,
+    next: Option<Bitboard>,
+}
+
+impl Iterator for Subsets {
+    type Item = Bitboard;
+    fn next(&mut self) -> Option<Bitboard> {
+        let b = self.next?;
+        let n = Bitboard(b.0.wrapping_sub(self.mask.0) & self.mask.0);
+        self.next = if n.zero() { None } else { Some(n) };
+        Some(b)
+    }
+}
+
 pub const FILE_BB: [Bitboard; 8] = [
     Bitboard(0x0101010101010101),
     Bitboard(0x0101010101010101 << 1),
@@ -280,3 +466,95 @@ impl fmt::Display for Bitboard {
         write!(f, "{s}")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::square::individual_squares::*;
+
+    #[test]
+    fn square_set_operations() {
+        let bb = Bitboard::from_squares(&[A1, H8]);
+        assert!(bb.contains(A1));
+        assert!(bb.contains(H8));
+        assert!(!bb.contains(B2));
+
+        assert!(bb.with(B2).contains(B2));
+        assert!(!bb.without(A1).contains(A1));
+        assert_eq!(bb.into_iter().collect::<Vec<_>>(), vec![A1, H8]);
+    }
+
+    #[test]
+    fn squares_in_order() {
+        let bb = Bitboard::from_squares(&[H8, C3, A1, E4]);
+        assert_eq!(bb.to_squares(), vec![A1, C3, E4, H8]);
+        assert_eq!(bb.first_square(), Some(A1));
+        assert_eq!(bb.last_square(), Some(H8));
+        assert_eq!(bb.without(H8).last_square(), Some(E4));
+
+        assert!(Bitboard::ZERO.to_squares().is_empty());
+        assert_eq!(Bitboard::ZERO.first_square(), None);
+        assert_eq!(Bitboard::ZERO.last_square(), None);
+    }
+
+    #[test]
+    fn checked_square_of_empty_board() {
+        assert_eq!(Bitboard::ZERO.get_square_checked(), None);
+        assert_eq!(Bitboard::from(D5).get_square_checked(), Some(D5));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "empty bitboard")]
+    fn get_square_of_empty_board_panics() {
+        Bitboard::ZERO.get_square();
+    }
+
+    #[test]
+    fn subsets_of_three_bits() {
+        let bb = Bitboard::from_squares(&[A1, C3, H8]);
+        let subsets = bb.subsets().collect::<Vec<_>>();
+        assert_eq!(subsets.len(), 8);
+        assert!(subsets.contains(&Bitboard::ZERO));
+        assert!(subsets.contains(&bb));
+        assert!(subsets.iter().all(|&s| (s & !bb).zero()));
+
+        assert!(bb.parity());
+        assert!(!bb.without(C3).parity());
+        assert!(!Bitboard::ZERO.parity());
+    }
+
+    #[test]
+    fn symmetries() {
+        let a1 = Bitboard::from(A1);
+        assert_eq!(a1.flip_vertical(), Bitboard::from(A8));
+        assert_eq!(a1.flip_horizontal(), Bitboard::from(H1));
+        assert_eq!(a1.rotate_180(), Bitboard::from(H8));
+        assert_eq!(a1.flip_anti_diagonal(), Bitboard::from(H8));
+        assert_eq!(Bitboard::from(B1).flip_diagonal(), Bitboard::from(A2));
+        assert_eq!(Bitboard::from(C2).flip_anti_diagonal(), Bitboard::from(G6));
+
+        let bb = Bitboard::from_squares(&[A1, C2, E7, H3]);
+        assert_eq!(bb.flip_diagonal().flip_diagonal(), bb);
+        assert_eq!(bb.flip_anti_diagonal().flip_anti_diagonal(), bb);
+        assert_eq!(bb.flip_vertical().flip_horizontal(), bb.rotate_180());
+        assert_eq!(Bitboard::from(File::B).flip_diagonal(), Bitboard::from(Rank::Two));
+    }
+
+    #[test]
+    fn pawn_spread_and_pushes() {
+        let rank_2 = Bitboard::from(Rank::Two);
+        assert_eq!(rank_2.pawn_attacks_spread(Color::White), Bitboard::from(Rank::Three));
+
+        let edge = Bitboard::from_squares(&[A2, H2]);
+        assert_eq!(edge.pawn_attacks_spread(Color::White), Bitboard::from_squares(&[B3, G3]));
+        assert_eq!(
+            Bitboard::from(A7).pawn_attacks_spread(Color::Black),
+            Bitboard::from(B6)
+        );
+
+        let empty = !Bitboard::from(E3);
+        assert_eq!(rank_2.pawn_pushes(Color::White, empty), Bitboard::from(Rank::Three).without(E3));
+        assert_eq!(Bitboard::from(D7).pawn_pushes(Color::Black, !Bitboard::ZERO), Bitboard::from(D6));
+    }
+}
@@ -18,6 +18,7 @@
 
 use std::fmt;
 
+use crate::filerank::File;
 use crate::piece::PType;
 use crate::square::Square;
 
@@ -36,6 +37,17 @@ pub enum MType {
 impl Move {
     pub const NULL: Self = Self(0);
 
+    // "No move", e.g. a hash entry where nothing raised alpha. NULL reads as
+    // a1a1, this sentinel can't be confused with a move that was never set.
+    #[inline]
+    pub const fn null_move() -> Self {
+        Self(1 | (1 << 6))
+    }
+    #[inline]
+    pub const fn is_null(self) -> bool {
+        self.0 == Self::null_move().0
+    }
+
     #[inline]
     pub const fn is_ok(self) -> bool {
         self.from().inner() != self.to().inner()
@@ -65,6 +77,38 @@ impl Move {
         Self(f6 | s6)
     }
 
+    // Only knights, bishops, rooks and queens can be promoted to
+    #[inline]
+    pub fn promotion(from: Square, to: Square, promo: PType) -> Self {
+        assert!(
+            promo != PType::Pawn && promo != PType::King,
+            "Invalid promotion piece {promo:?}"
+        );
+        Self::new(from, to).add_promo(promo)
+    }
+    // Encoded as king takes own rook, since in Chess960 the king can
+    // castle without leaving its square
+    #[inline]
+    pub fn castle(king: Square, rook: Square) -> Self {
+        Self::new(king, rook).add_type(MType::Castle)
+    }
+    // Where the king and rook of a castling move end up: g- and f-file
+    // for a rook on the king's right, c- and d-file for one on its left
+    #[inline]
+    pub fn castle_destinations(self) -> (Square, Square) {
+        debug_assert_eq!(self.kind(), MType::Castle);
+        let rank = self.from().rank();
+        if self.to().file() > self.from().file() {
+            (Square::create(File::G, rank), Square::create(File::F, rank))
+        } else {
+            (Square::create(File::C, rank), Square::create(File::D, rank))
+        }
+    }
+    #[inline]
+    pub fn en_passant(from: Square, to: Square) -> Self {
+        Self::new(from, to).add_type(MType::EnPassant)
+    }
+
     #[inline]
     pub const fn add_type(self, ty: MType) -> Self {
         Self(self.0 | ((ty as u32) << 12))
@@ -88,11 +132,59 @@ impl Move {
 
 impl fmt::Display for Move {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_null() {
+            return write!(f, "0000");
+        }
         let prom = if self.is_ok() && self.kind() == MType::Promotion {
             char::from(self.promo()).to_string()
         } else {
             String::new()
         };
+        // Standard castling prints as the usual e1g1, Chess960 castling as
+        // king takes rook
+        let standard = self.from().file() == File::E
+            && (self.to().file() == File::A || self.to().file() == File::H);
+        if self.is_ok() && self.kind() == MType::Castle && standard {
+            return write!(f, "{}{}", self.from(), self.castle_destinations().0);
+        }
         write!(f, "{}{}{}", self.from(), self.to(), prom)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::square::individual_squares::*;
+
+    #[test]
+    fn special_constructors() {
+        let m = Move::promotion(E7, E8, PType::Knight);
+        assert_eq!(
+            (m.from(), m.to(), m.kind(), m.promo()),
+            (E7, E8, MType::Promotion, PType::Knight)
+        );
+        assert_eq!(m, Move::new(E7, E8).add_promo(PType::Knight));
+        assert_eq!(Move::castle(E1, H1).kind(), MType::Castle);
+        assert_eq!(Move::castle(E1, H1).castle_destinations(), (G1, F1));
+        assert_eq!(Move::castle(B8, A8).castle_destinations(), (C8, D8));
+        assert_eq!(Move::castle(E1, A1).to_string(), "e1c1");
+        assert_eq!(Move::castle(G1, H1).to_string(), "g1h1");
+        assert_eq!(Move::en_passant(E5, D6).kind(), MType::EnPassant);
+        assert_eq!(Move::en_passant(E5, D6).promo(), PType::Pawn);
+    }
+
+    #[test]
+    fn null_move_sentinel() {
+        let null = Move::null_move();
+        assert!(null.is_null() && !null.is_ok());
+        assert!(!Move::NULL.is_null());
+        assert_ne!(null, Move::new(B1, B2));
+        assert_eq!(null.to_string(), "0000");
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid promotion piece")]
+    fn promotion_to_king_panics() {
+        Move::promotion(E7, E8, PType::King);
+    }
+}
@@ -27,15 +27,37 @@ pub enum Color {
 }
 
 impl Color {
-    pub fn pawn_push(self) -> Box<fn(Bitboard) -> Bitboard> {
-        Box::new(match self {
+    pub const fn pawn_push(self) -> fn(Bitboard) -> Bitboard {
+        match self {
             Self::White => push_n,
             Self::Black => push_s,
-        })
+        }
     }
     pub fn persp(self, value: i32) -> i32 {
         value * (1 - 2 * self as i32)
     }
+
+    pub const fn from_fen_char(c: char) -> Option<Self> {
+        match c {
+            'w' => Some(Self::White),
+            'b' => Some(Self::Black),
+            _ => None,
+        }
+    }
+    pub const fn to_fen_char(self) -> char {
+        match self {
+            Self::White => 'w',
+            Self::Black => 'b',
+        }
+    }
+
+    pub const fn from_index(idx: usize) -> Self {
+        debug_assert!(idx < 2);
+        match idx {
+            0 => Self::White,
+            _ => Self::Black,
+        }
+    }
 }
 
 fn push_n(b: Bitboard) -> Bitboard {
@@ -55,3 +77,19 @@ impl Not for Color {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Color;
+
+    #[test]
+    fn fen_char_conversions() {
+        for c in [Color::White, Color::Black] {
+            assert_eq!(Color::from_fen_char(c.to_fen_char()), Some(c));
+            assert_eq!(Color::from_index(c as usize), c);
+        }
+        assert_eq!(Color::White.to_fen_char(), 'w');
+        assert_eq!(Color::Black.to_fen_char(), 'b');
+        assert_eq!(Color::from_fen_char('x'), None);
+    }
+}
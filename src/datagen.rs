@@ -0,0 +1,203 @@
+/*
+    ChessLib, a UCI chess engine
+    Copyright (C) 2023 Sam Price
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::prelude::*;
+use crate::prng::Prng;
+
+// Games that run this long are adjudicated as draws
+const MAX_GAME_PLIES: usize = 400;
+
+// When an engine-vs-engine game may be stopped early. Evals are
+// White-relative centipawns, one per ply, the latest last.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Adjudication {
+    // Draw once this many plies in a row stayed within draw_score of 0,
+    // with no more than draw_material left on the board (both sides)
+    pub draw_plies: usize,
+    pub draw_score: i32,
+    pub draw_material: i32,
+    // Win once this many plies in a row were at least win_score for one side
+    pub win_plies: usize,
+    pub win_score: i32,
+}
+
+impl Default for Adjudication {
+    fn default() -> Self {
+        Self {
+            draw_plies: 16,
+            draw_score: 10,
+            draw_material: 1300,
+            win_plies: 8,
+            win_score: 1000,
+        }
+    }
+}
+
+impl Position {
+    // Plays random games from the start position and writes every quiet
+    // position as `FEN;result`, with the result from White's point of view.
+    // Returns the number of lines written.
+    pub fn export_fens_with_result<P: AsRef<Path>>(
+        rng: &mut Prng,
+        games: usize,
+        path: P,
+    ) -> io::Result<usize> {
+        let mut out = BufWriter::new(File::create(path)?);
+        let mut written = 0;
+        let mut moves = MoveList::new();
+
+        for _ in 0..games {
+            let mut pos = Position::startpos();
+            let mut fens = Vec::new();
+            let mut plies = 0;
+
+            let result = loop {
+                match (pos.status_with_moves(&mut moves), pos.to_move()) {
+                    (GameResult::Ongoing, _) => (),
+                    (GameResult::Stalemate, _) => break "0.5",
+                    (GameResult::Checkmate, Color::White) => break "0",
+                    (GameResult::Checkmate, Color::Black) => break "1",
+                }
+                if pos.state().rule50() >= 100 || plies >= MAX_GAME_PLIES {
+                    break "0.5";
+                }
+
+                if pos.is_quiet() {
+                    fens.push(pos.fen());
+                }
+
+                let m = moves.get(rng.sample() as usize % moves.len());
+                pos.do_move(m);
+                plies += 1;
+            };
+
+            for fen in fens {
+                writeln!(out, "{fen};{result}")?;
+                written += 1;
+            }
+        }
+
+        out.flush()?;
+        Ok(written)
+    }
+
+    pub fn should_adjudicate_draw(&self, history: &[i32], rules: &Adjudication) -> bool {
+        let (white, black) = self.total_material();
+        let material = white + black;
+        rules.draw_plies > 0
+            && history.len() >= rules.draw_plies
+            && material <= rules.draw_material
+            && history[history.len() - rules.draw_plies..]
+                .iter()
+                .all(|e| e.abs() <= rules.draw_score)
+    }
+
+    // The side that is winning, if it has been for long enough
+    pub fn should_adjudicate_win(&self, history: &[i32], rules: &Adjudication) -> Option<Color> {
+        if rules.win_plies == 0 || history.len() < rules.win_plies {
+            return None;
+        }
+        let recent = &history[history.len() - rules.win_plies..];
+        if recent.iter().all(|&e| e >= rules.win_score) {
+            Some(Color::White)
+        } else if recent.iter().all(|&e| e <= -rules.win_score) {
+            Some(Color::Black)
+        } else {
+            None
+        }
+    }
+
+    // Quiet means no check and no capture that obviously wins material,
+    // so the final result is a fair label for the position.
+    fn is_quiet(&self) -> bool {
+        if self.in_check() {
+            return false;
+        }
+
+        let us = self.to_move();
+        let mut captures = MoveList::new();
+        generate_for_legal(self, GenType::Captures, &mut captures);
+
+        for i in 0..captures.len() {
+            let m = captures.get(i);
+            let victim = self.piece_on(m.to());
+            if !victim.is_ok() {
+                continue;
+            }
+            let attacker = self.piece_on(m.from()).kind();
+            let defended = self.attackers_by(m.to(), self.all(), !us).nonzero();
+            if !defended || victim.kind().value() > attacker.value() {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn adjudicate_quiet_endgames() {
+        crate::init_for_tests();
+        let rules = Adjudication::default();
+        let flat = [4, -3, 0, 7, -10, 2, 0, 1, -1, 5, 3, -2, 0, 0, 6, -4];
+
+        let rooks = Position::from_str("4k3/r7/8/8/8/8/7R/4K3 w - - 0 1").unwrap();
+        assert!(rooks.should_adjudicate_draw(&flat, &rules));
+        assert!(!rooks.should_adjudicate_draw(&flat[1..], &rules));
+        let mut swing = flat;
+        swing[9] = 40;
+        assert!(!rooks.should_adjudicate_draw(&swing, &rules));
+
+        // Level, but far too much left on the board
+        assert!(!Position::startpos().should_adjudicate_draw(&flat, &rules));
+
+        let crushing = [1200, 1100, 1500, 1000, 1300, 1400, 1250, 1600];
+        assert_eq!(rooks.should_adjudicate_win(&crushing, &rules), Some(Color::White));
+        let negated = crushing.map(|e| -e);
+        assert_eq!(rooks.should_adjudicate_win(&negated, &rules), Some(Color::Black));
+        assert_eq!(rooks.should_adjudicate_win(&flat, &rules), None);
+    }
+
+    #[test]
+    fn export_writes_labelled_fens() {
+        crate::init_for_tests();
+        let path = std::env::temp_dir().join("chess_lib_export_test.txt");
+        let mut rng = Prng::new(0xdeadbeef);
+
+        let n = Position::export_fens_with_result(&mut rng, 2, &path).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(n > 0);
+        assert_eq!(contents.lines().count(), n);
+        for line in contents.lines() {
+            let (fen, result) = line.split_once(';').unwrap();
+            assert!(["0", "0.5", "1"].contains(&result));
+            assert!(Position::from_str(fen).is_ok());
+        }
+    }
+}
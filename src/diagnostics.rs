@@ -73,6 +73,15 @@ pub fn add_quiesce_depth() {
     }
 }
 
+// Keeps the deepest quiescence ply seen
+#[cfg(feature = "diagnostics")]
+#[inline(always)]
+pub fn record_quiesce_depth(depth: usize) {
+    unsafe {
+        QUIESCE_DEPTH = QUIESCE_DEPTH.max(depth);
+    }
+}
+
 #[cfg(feature = "diagnostics")]
 #[inline(always)]
 pub fn add_alphabeta_leaf_nodes() {
@@ -90,7 +99,7 @@ pub fn get_beta_cutoffs() -> usize {
 #[cfg(feature = "diagnostics")]
 #[inline(always)]
 pub fn get_quiesce_depth() -> usize {
-    unsafe { BETA_CUTOFF_COUNT }
+    unsafe { QUIESCE_DEPTH }
 }
 
 #[cfg(feature = "diagnostics")]
@@ -133,6 +142,10 @@ pub fn add_beta_cutoffs() {}
 #[inline(always)]
 pub fn add_quiesce_depth() {}
 
+#[cfg(not(feature = "diagnostics"))]
+#[inline(always)]
+pub fn record_quiesce_depth(_depth: usize) {}
+
 #[cfg(not(feature = "diagnostics"))]
 #[inline(always)]
 pub fn add_alphabeta_leaf_nodes() {}
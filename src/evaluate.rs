@@ -16,53 +16,492 @@
     along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
 
+use std::fmt;
 use std::thread;
 
 use crate::diagnostics;
-use crate::moveorder::order_moves;
-use crate::prelude::{generate_for, generate_legal, MoveList};
+use crate::moveorder::{self, order_moves_after, History};
+use crate::prelude::{generate_for_legal, generate_legal, MoveList};
 use crate::prelude::{Color, Position};
 use crate::prelude::{GenType, Move};
+use crate::prelude::{pawn_attack, Bitboard, PType, Rank, Square, FILE_BB, RANK_BB};
+use crate::tt::{self, Bound};
 
 use crate::debug;
 
-// These contants make it easier to read.
+// Scores are in centipawns (a pawn is worth 100) from the side to move's
+// point of view, unless a function says it is White-relative.
+
+// These contants make it easier to read. They are the search window's
+// infinities, symmetric so that negating either one can't overflow.
 pub const MAX_RAT: i32 = i32::MAX;
-pub const MIN_RAT: i32 = -i32::MAX;
+pub const MIN_RAT: i32 = -MAX_RAT;
+
+// Being checkmated `ply` plies from the root scores -(MATE - ply), so a
+// quicker mate is worth more to the winner. Anything beyond MATE_IN_MAX_PLY
+// is a mate score rather than a material count.
+pub const MATE: i32 = 32_000;
+pub const MATE_IN_MAX_PLY: i32 = MATE - crate::MAX_PLY as i32;
+pub const DRAW: i32 = 0;
+
+#[inline]
+pub const fn mated_in(ply: usize) -> i32 {
+    -MATE + ply as i32
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct Score(pub i32);
+
+impl Score {
+    #[inline]
+    pub const fn is_mate(self) -> bool {
+        self.0.abs() >= MATE_IN_MAX_PLY
+    }
+
+    // The value in centipawns, mate scores clamped to the mate bound
+    #[inline]
+    pub const fn to_cp(self) -> i32 {
+        if self.0 > MATE_IN_MAX_PLY {
+            MATE_IN_MAX_PLY
+        } else if self.0 < -MATE_IN_MAX_PLY {
+            -MATE_IN_MAX_PLY
+        } else {
+            self.0
+        }
+    }
+
+    // Moves until mate, negative when we are the one getting mated. Zero
+    // only for a root that is already checkmated.
+    pub const fn mate_in(self) -> Option<i32> {
+        if !self.is_mate() {
+            return None;
+        }
+        let plies = MATE - self.0.abs();
+        let moves = (plies + 1) / 2;
+        Some(if self.0 > 0 { moves } else { -moves })
+    }
+}
+
+// UCI style: "cp 35" or "mate -2"
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.mate_in() {
+            Some(m) => write!(f, "mate {m}"),
+            None => write!(f, "cp {}", self.0),
+        }
+    }
+}
+
+// The optional search features, chosen per search
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchOptions {
+    // Late-move pruning of quiet moves with bad history near the leaves
+    pub late_move_pruning: bool,
+    // Quiet checks searched at the first ply of quiescence
+    pub quiesce_checks: bool,
+    // Extend the best-ordered move when every alternative is far worse
+    pub singular_extensions: bool,
+    // How the static evaluation scales its terms
+    pub weights: Weights,
+}
+
+impl SearchOptions {
+    pub const DEFAULT: Self = Self {
+        late_move_pruning: true,
+        quiesce_checks: true,
+        singular_extensions: false,
+        weights: Weights::DEFAULT,
+    };
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+const LMP_MAX_DEPTH: usize = 3;
+const LMP_BASE_MOVES: usize = 3;
+
+// Plies of captures (and evasions) quiescence looks at past the horizon
+const MAX_QUIESCE_DEPTH: usize = 8;
+
+const SINGULAR_MIN_DEPTH: usize = 4;
+const SINGULAR_MARGIN: i32 = 150;
+
+fn material(pos: &Position, color: Color) -> i32 {
+    pos.material(color)
+}
+
+// Bonus for having the move
+const TEMPO: i32 = 10;
+
+fn tempo(pos: &Position, color: Color) -> i32 {
+    if pos.to_move() == color {
+        TEMPO
+    } else {
+        0
+    }
+}
+
+const ROOK_OPEN_FILE: i32 = 25;
+const ROOK_HALF_OPEN_FILE: i32 = 10;
+const ROOK_ON_SEVENTH: i32 = 20;
 
-fn material_balance(pos: &Position) -> i32 {
-    let white_material = pos.material(Color::White);
-    let black_material = pos.material(Color::Black);
+fn rook_placement(pos: &Position, color: Color) -> i32 {
+    let mut score = 0;
+    let ours = pos.spec(PType::Pawn, color);
+    let theirs = pos.spec(PType::Pawn, !color);
+    let seventh = RANK_BB[Rank::Seven.relative(color) as usize];
+
+    for s in pos.spec(PType::Rook, color) {
+        let file = FILE_BB[s.file() as usize];
+        if (file & ours).zero() {
+            score += if (file & theirs).zero() {
+                ROOK_OPEN_FILE
+            } else {
+                ROOK_HALF_OPEN_FILE
+            };
+        }
+        if (seventh & s).nonzero() {
+            score += ROOK_ON_SEVENTH;
+        }
+    }
 
-    white_material - black_material
+    score
+}
+
+// The ranks strictly in front of `s`, as seen by `color`
+fn forward_ranks(color: Color, s: Square) -> Bitboard {
+    let rank = s.rank() as u32;
+    match color {
+        Color::White => Bitboard::new(u64::MAX.checked_shl(8 * (rank + 1)).unwrap_or(0)),
+        Color::Black => Bitboard::new((1u64 << (8 * rank)) - 1),
+    }
+}
+
+fn adjacent_files(s: Square) -> Bitboard {
+    let f = s.file() as usize;
+    let left = if f > 0 { FILE_BB[f - 1] } else { Bitboard::ZERO };
+    let right = if f < 7 { FILE_BB[f + 1] } else { Bitboard::ZERO };
+    left | right
+}
+
+// Squares from which an enemy pawn could ever attack `s`
+fn pawn_attack_span(color: Color, s: Square) -> Bitboard {
+    forward_ranks(color, s) & adjacent_files(s)
+}
+
+// Squares an enemy pawn would have to be on to stop or take a pawn on `s`
+fn passed_pawn_mask(color: Color, s: Square) -> Bitboard {
+    forward_ranks(color, s) & (adjacent_files(s) | FILE_BB[s.file() as usize])
+}
+
+// By relative rank
+const PASSED_PAWN: [i32; 8] = [0, 5, 10, 20, 35, 60, 100, 0];
+const CONNECTED_PASSER: i32 = 15;
+const PROTECTED_PASSER: i32 = 10;
+const BLOCKADED_PASSER: i32 = 20;
+
+// Passed pawns, more so when side by side with another passer or guarded
+// by a pawn, less so when the square in front is held by the enemy
+fn passed_pawns(pos: &Position, color: Color) -> i32 {
+    let ours = pos.spec(PType::Pawn, color);
+    let theirs = pos.spec(PType::Pawn, !color);
+    let passers = ours
+        .into_iter()
+        .filter(|&s| (passed_pawn_mask(color, s) & theirs).zero())
+        .fold(Bitboard::ZERO, |b, s| b | s);
+
+    let mut score = 0;
+    for s in passers {
+        score += PASSED_PAWN[s.relative_rank(color) as usize];
+        if (adjacent_files(s) & passers).nonzero() {
+            score += CONNECTED_PASSER;
+        }
+        if (pawn_attack(s, !color) & ours).nonzero() {
+            score += PROTECTED_PASSER;
+        }
+        let stop = forward_ranks(color, s) & FILE_BB[s.file() as usize];
+        let stop = if color == Color::White { stop.first_square() } else { stop.last_square() };
+        if let Some(stop) = stop {
+            if pos.color(!color).contains(stop) || pos.is_attacked_by(stop, pos.all(), !color) {
+                score -= BLOCKADED_PASSER;
+            }
+        }
+    }
+    score
+}
+
+// White-minus-Black passed pawn score
+pub fn passed_pawn_score(pos: &Position) -> i32 {
+    passed_pawns(pos, Color::White) - passed_pawns(pos, Color::Black)
+}
+
+const KNIGHT_OUTPOST: i32 = 30;
+
+// Knights on the 4th to 6th ranks, guarded by a pawn, that no enemy pawn can chase away
+fn knight_outposts(pos: &Position, color: Color) -> i32 {
+    let ours = pos.spec(PType::Pawn, color);
+    let theirs = pos.spec(PType::Pawn, !color);
+
+    let mut score = 0;
+    for s in pos.spec(PType::Knight, color) {
+        if matches!(s.relative_rank(color), Rank::Four | Rank::Five | Rank::Six)
+            && (pawn_attack(s, !color) & ours).nonzero()
+            && (pawn_attack_span(color, s) & theirs).zero()
+        {
+            score += KNIGHT_OUTPOST;
+        }
+    }
+    score
+}
+
+const LIGHT_SQUARES: Bitboard = Bitboard::new(0x55AA_55AA_55AA_55AA);
+const BAD_BISHOP_PAWN: i32 = 4;
+
+// Penalty for each of our pawns stuck on the same colour squares as a bishop
+fn bad_bishops(pos: &Position, color: Color) -> i32 {
+    let ours = pos.spec(PType::Pawn, color);
+    let mut score = 0;
+    for s in pos.spec(PType::Bishop, color) {
+        let shade = if LIGHT_SQUARES.contains(s) { LIGHT_SQUARES } else { !LIGHT_SQUARES };
+        score -= BAD_BISHOP_PAWN * (ours & shade).popcnt() as i32;
+    }
+    score
+}
+
+// A threatened piece is worth this fraction of its value
+const THREAT_DIVISOR: i32 = 4;
+
+// Enemy pieces we attack that can't be traded off evenly: either our
+// cheapest attacker is worth less, or nothing defends them. A quick
+// stand-in for running SEE on every capture.
+fn threatened_pieces(pos: &Position, color: Color) -> i32 {
+    let mut score = 0;
+    for s in pos.color(!color).and_not(pos.piece(PType::King)) {
+        let attackers = pos.attackers_by(s, pos.all(), color);
+        if attackers.zero() {
+            continue;
+        }
+        let victim = pos.piece_on(s).value();
+        let cheapest = [PType::Pawn, PType::Knight, PType::Bishop, PType::Rook, PType::Queen]
+            .into_iter()
+            .find(|&pt| (attackers & pos.piece(pt)).nonzero())
+            .map_or(PType::King.value(), PType::value);
+
+        if cheapest < victim || !pos.is_attacked_by(s, pos.all(), !color) {
+            score += victim / THREAT_DIVISOR;
+        }
+    }
+    score
+}
+
+// White-minus-Black threat score
+pub fn threats(pos: &Position) -> i32 {
+    threatened_pieces(pos, Color::White) - threatened_pieces(pos, Color::Black)
+}
+
+// Indexed by Square::weight_map_idx, rank by rank from our back rank and
+// files a to d (e to h mirror them)
+#[rustfmt::skip]
+const KING_SHELTER: [i32; 32] = [
+     20,  30,  10,   0,
+     10,  10,  -5, -10,
+    -15, -20, -25, -30,
+    -25, -30, -35, -40,
+    -30, -35, -40, -45,
+    -30, -35, -40, -45,
+    -30, -35, -40, -45,
+    -30, -35, -40, -45,
+];
+#[rustfmt::skip]
+const KING_CENTRALITY: [i32; 32] = [
+    -40, -25, -15, -10,
+    -25, -10,   0,   5,
+    -15,   0,  10,  15,
+    -10,   5,  15,  25,
+    -10,   5,  15,  25,
+    -15,   0,  10,  15,
+    -25, -10,   0,   5,
+    -40, -25, -15, -10,
+];
+
+// Tucked away while there are pieces to attack it
+fn king_shelter(pos: &Position, color: Color) -> i32 {
+    KING_SHELTER[pos.king(color).weight_map_idx(color)]
+}
+
+// Central and active once the board empties
+fn king_activity(pos: &Position, color: Color) -> i32 {
+    KING_CENTRALITY[pos.king(color).weight_map_idx(color)]
+}
+
+type EvalTerm = fn(&Position, Color) -> i32;
+
+// Every evaluation term, each scored from one side's point of view
+const EVAL_TERMS: [(&str, EvalTerm); 9] = [
+    ("Material", material),
+    ("Passed pawns", passed_pawns),
+    ("Rooks", rook_placement),
+    ("Outposts", knight_outposts),
+    ("Bishops", bad_bishops),
+    ("Threats", threatened_pieces),
+    ("King safety", king_shelter),
+    ("King activity", king_activity),
+    ("Tempo", tempo),
+];
+
+// Percentages each of EVAL_TERMS is scaled by, in the middlegame and in
+// the endgame. The two are blended by game_phase.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Weights {
+    pub terms: [(i32, i32); EVAL_TERMS.len()],
+}
+
+impl Weights {
+    pub const DEFAULT: Self = Self {
+        terms: [
+            (100, 100),
+            (100, 100),
+            (100, 100),
+            (100, 100),
+            (100, 100),
+            (100, 100),
+            (100, 0),
+            (0, 100),
+            (100, 100),
+        ],
+    };
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+pub const MAX_PHASE: i32 = 24;
+
+// MAX_PHASE with all the pieces on, 0 with only kings and pawns left
+pub fn game_phase(pos: &Position) -> i32 {
+    let minors = pos.piece_2t(PType::Knight, PType::Bishop).popcnt() as i32;
+    let rooks = pos.piece(PType::Rook).popcnt() as i32;
+    let queens = pos.piece(PType::Queen).popcnt() as i32;
+    (minors + 2 * rooks + 4 * queens).min(MAX_PHASE)
+}
+
+// One side's score for a term, scaled and blended by the phase
+#[inline]
+fn tapered(value: i32, (mg, eg): (i32, i32), phase: i32) -> i32 {
+    value * (mg * phase + eg * (MAX_PHASE - phase)) / (100 * MAX_PHASE)
+}
+
+// (name, white, black) for every term, already tapered
+fn term_scores<'a>(
+    pos: &'a Position, weights: &Weights
+) -> impl Iterator<Item = (&'static str, i32, i32)> + 'a {
+    let phase = game_phase(pos);
+    EVAL_TERMS.iter().zip(weights.terms).map(move |(&(name, term), w)| {
+        let white = tapered(term(pos, Color::White), w, phase);
+        let black = tapered(term(pos, Color::Black), w, phase);
+        (name, white, black)
+    })
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct EvalTrace {
+    // (name, white, black) for every term
+    pub terms: Vec<(&'static str, i32, i32)>,
+}
+
+impl EvalTrace {
+    pub fn total(&self) -> i32 {
+        self.terms.iter().map(|&(_, w, b)| w - b).sum()
+    }
+}
+
+impl fmt::Display for EvalTrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:>13} | {:>7} | {:>7} | {:>7}", "Term", "White", "Black", "Total")?;
+        writeln!(f, "{:-<14}+{:-<9}+{:-<9}+{:-<8}", "", "", "", "")?;
+        for &(name, w, b) in &self.terms {
+            writeln!(f, "{name:>13} | {w:>7} | {b:>7} | {:>7}", w - b)?;
+        }
+        writeln!(f, "{:-<14}+{:-<9}+{:-<9}+{:-<8}", "", "", "", "")?;
+        writeln!(f, "{:>13} | {:>7} | {:>7} | {:>7}", "Final", "", "", self.total())
+    }
+}
+
+// Breakdown of the static evaluation of a non-terminal position
+pub fn trace(pos: &Position) -> EvalTrace {
+    trace_with(pos, &Weights::DEFAULT)
+}
+
+pub fn trace_with(pos: &Position, weights: &Weights) -> EvalTrace {
+    EvalTrace {
+        terms: term_scores(pos, weights).collect(),
+    }
+}
+
+impl Position {
+    #[inline]
+    pub fn eval_trace(&self) -> EvalTrace {
+        trace(self)
+    }
 }
 
 pub fn static_evaluate(pos: &Position) -> i32 {
+    evaluate_with(pos, &Weights::DEFAULT)
+}
+
+// static_evaluate with the terms scaled by `weights` instead of the defaults
+pub fn evaluate_with(pos: &Position, weights: &Weights) -> i32 {
     let mut move_list = MoveList::new();
     generate_legal::<false>(pos, &mut move_list);
-    if move_list.len() > 0 {
-        material_balance(pos)
+    if !move_list.is_empty() {
+        term_scores(pos, weights).map(|(_, w, b)| w - b).sum()
     } else if pos.in_check() {
-        MIN_RAT
+        // White-relative like the terms, so mated is bad for whoever is to move
+        pos.to_move().persp(-MATE)
     } else {
-        0
+        DRAW
     }
 }
 
-pub fn minimax<const ROOT: bool>(pos: &mut Position, best_move: &mut Move, depth: usize) -> i32 {
-    if depth == 0 {
-        return pos.to_move().persp(static_evaluate(pos));
+// The side to move's static score `ply` plies from the root. Static
+// evaluation can't know the ply, so its mates are counted from here.
+fn eval_at(pos: &Position, ply: usize, weights: &Weights) -> i32 {
+    match pos.to_move().persp(evaluate_with(pos, weights)) {
+        e if e == -MATE => mated_in(ply),
+        e => e,
     }
+}
+
+pub fn minimax<const ROOT: bool>(pos: &mut Position, best_move: &mut Move, depth: usize) -> i32 {
+    minimax_internal::<ROOT>(pos, best_move, depth, 0)
+}
+
+fn minimax_internal<const ROOT: bool>(
+    pos: &mut Position, best_move: &mut Move, depth: usize, ply: usize
+) -> i32 {
     let mut move_list = MoveList::new();
-    let mut best_rat = i32::MIN;
     generate_legal::<false>(pos, &mut move_list);
+    // Mate and stalemate are scored here too, looping over no moves would
+    // hand back the initial best_rat
+    if depth == 0 || move_list.is_empty() {
+        return eval_at(pos, ply, &Weights::DEFAULT);
+    }
+
+    // Never i32::MIN, the caller negates whatever comes back
+    let mut best_rat = MIN_RAT;
     for i in 0..move_list.len() {
         let m = move_list.get(i);
         if !pos.is_legal(m) {
             continue;
         }
         pos.do_move(m);
-        let e = -minimax::<false>(pos, best_move, depth - 1);
+        let e = -minimax_internal::<false>(pos, best_move, depth - 1, ply + 1);
         if e > best_rat {
             best_rat = e;
             if ROOT {
@@ -76,99 +515,619 @@ pub fn minimax<const ROOT: bool>(pos: &mut Position, best_move: &mut Move, depth
 }
 
 pub fn alpha_beta(pos: &mut Position, best_move: &mut Move, depth: usize) -> i32 {
-    let tm = pos.to_move();
-    alpha_beta_internal::<true>(pos, best_move, depth,
-                                tm.persp(MIN_RAT),
-                                tm.persp(MAX_RAT))
+    alpha_beta_with(pos, best_move, depth, &SearchOptions::DEFAULT)
 }
 
-fn alpha_beta_internal<const ROOT: bool>(
-    pos: &mut Position, best_move: &mut Move, depth: usize,
-    alpha: i32, beta: i32
+pub fn alpha_beta_with(
+    pos: &mut Position, best_move: &mut Move, depth: usize, options: &SearchOptions
 ) -> i32 {
+    let mut info = AbInfo::new(*best_move, *options);
+    // Scores are relative to the side to move, so the window is too
+    let root = Node {
+        depth,
+        ply: 0,
+        alpha: MIN_RAT,
+        beta: MAX_RAT,
+        pv_node: true,
+        extended: false,
+        prev: Move::NULL,
+    };
+    let score = alpha_beta_internal::<true>(pos, root, &mut info);
+    *best_move = info.best_move;
+    score
+}
+
+// The window and flags of one alpha_beta node
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    depth: usize,
+    // Plies from the root
+    ply: usize,
+    alpha: i32,
+    beta: i32,
+    pv_node: bool,
+    // A singular extension was already spent on this line
+    extended: bool,
+    // The move that led here, for the countermove lookup
+    prev: Move,
+}
+
+impl Node {
+    // The node reached by playing `m` here, with the window flipped
+    fn child(&self, m: Move, depth: usize, alpha: i32, beta: i32, pv_node: bool) -> Self {
+        Self {
+            depth,
+            ply: self.ply + 1,
+            alpha: -beta,
+            beta: -alpha,
+            pv_node,
+            extended: self.extended,
+            prev: m,
+        }
+    }
+}
+
+// Bookkeeping shared by every node of one alpha_beta search
+struct AbInfo {
+    history: History,
+    options: SearchOptions,
+    best_move: Move,
+}
+
+impl AbInfo {
+    fn new(best_move: Move, options: SearchOptions) -> Self {
+        Self {
+            history: History::new(),
+            options,
+            best_move,
+        }
+    }
+}
+
+fn alpha_beta_internal<const ROOT: bool>(pos: &mut Position, node: Node, info: &mut AbInfo) -> i32 {
     if ROOT {
         diagnostics::reset_beta_cutoffs();
         diagnostics::reset_alphabeta_leaf_nodes();
+        diagnostics::reset_quiesce_depth();
+        tt::new_search();
     }
 
+    let Node { depth, beta, pv_node, extended, prev, .. } = node;
     let mut move_list = MoveList::new();
     generate_legal::<false>(pos, &mut move_list);
 
-    if move_list.len() == 0 || depth == 0 {
+    if move_list.is_empty() || depth == 0 {
         diagnostics::add_alphabeta_leaf_nodes();
     }
 
-    if move_list.len() == 0 {
-        return pos.to_move().persp(static_evaluate(pos));
+    if move_list.is_empty() {
+        return if pos.in_check() { mated_in(node.ply) } else { DRAW };
     } else if depth == 0 {
-        return quiescence(pos, alpha, beta);
+        return quiescence(pos, node.alpha, beta, node.ply, &info.options);
     }
 
-    order_moves(pos, &mut move_list);
-    let mut alpha = alpha;
+    order_moves_after(pos, &mut move_list, &info.history, prev);
+    let mut alpha = node.alpha;
+    // At most one singular extension per line keeps the tree finite
+    let extend_first = info.options.singular_extensions
+        && !extended
+        && depth >= SINGULAR_MIN_DEPTH
+        && is_singular(pos, &move_list, &node, info);
+    let can_prune =
+        info.options.late_move_pruning && !pv_node && !pos.in_check() && depth <= LMP_MAX_DEPTH;
+    let mut quiets_tried = Vec::new();
+    let mut node_best = Move::null_move();
+    // Fail-soft: the best score is returned even when outside the window
+    let mut best_score = MIN_RAT;
 
     for i in 0..move_list.len() {
         let m = move_list.get(i);
+        let quiet = moveorder::is_quiet(pos, m);
+
+        if can_prune
+            && quiet
+            && i >= LMP_BASE_MOVES + depth * depth
+            && info.history.history(m) < 0
+            && !pos.gives_check(m)
+        {
+            continue;
+        }
+
+        let child_depth = if i == 0 && extend_first { depth } else { depth - 1 };
+        let mut child = node.child(m, child_depth, alpha, beta, pv_node && i == 0);
+        child.extended |= extend_first;
         pos.do_move(m);
-        let se = -alpha_beta_internal::<false>(pos, best_move, depth - 1, -beta, -alpha);
+        let se = -alpha_beta_internal::<false>(pos, child, info);
         pos.undo_move(m);
 
+        if se > best_score {
+            best_score = se;
+            if ROOT {
+                info.best_move = m;
+            }
+        }
+
         if se >= beta {
             diagnostics::add_beta_cutoffs();
-            return beta;
+            if quiet {
+                let bonus = (depth * depth) as i32;
+                info.history.update_history(m, bonus);
+                info.history.update_countermove(prev, m);
+                for q in quiets_tried {
+                    info.history.update_history(q, -bonus);
+                }
+            }
+            tt::store(pos.state().key(), m, se, depth, Bound::Lower);
+            return se;
+        }
+        if quiet {
+            quiets_tried.push(m);
         }
 
         if se > alpha {
             alpha = se;
+            node_best = m;
             if ROOT {
                 debug!("ROOT: {} {} {}", se, alpha, m);
-                *best_move = m;
             }
         }
     }
 
-    alpha
+    let bound = if node_best.is_ok() { Bound::Exact } else { Bound::Upper };
+    tt::store(pos.state().key(), node_best, best_score, depth, bound);
+    best_score
 }
 
-pub(crate) fn quiescence(pos: &mut Position, alpha: i32, beta: i32) -> i32 {
-    let stand_pat = pos.to_move().persp(static_evaluate(pos));
-    let mut alpha = alpha;
+// The first ordered move (the hash move, if any) is the candidate.
+// It is singular if a reduced search of every other move fails low
+// against the candidate's own reduced score minus a margin.
+fn is_singular(pos: &mut Position, move_list: &MoveList, node: &Node, info: &mut AbInfo) -> bool {
+    if move_list.len() < 2 {
+        return false;
+    }
+    // Searching with `reduced` as the parent keeps both the depth and the
+    // extension flag of every child
+    let reduced = Node { depth: node.depth / 2, extended: true, ..*node };
 
-    if stand_pat >= beta {
-        diagnostics::add_beta_cutoffs();
-        return beta;
+    let candidate = move_list.get(0);
+    pos.do_move(candidate);
+    let child = reduced.child(candidate, reduced.depth, MIN_RAT, MAX_RAT, false);
+    let candidate_score = -alpha_beta_internal::<false>(pos, child, info);
+    pos.undo_move(candidate);
+
+    // Mate scores are already decisive
+    if Score(candidate_score).is_mate() {
+        return false;
     }
 
-    if stand_pat > alpha {
-        alpha = stand_pat;
+    let rbeta = candidate_score - SINGULAR_MARGIN;
+    for i in 1..move_list.len() {
+        let m = move_list.get(i);
+        pos.do_move(m);
+        let child = reduced.child(m, reduced.depth, rbeta - 1, rbeta, false);
+        let e = -alpha_beta_internal::<false>(pos, child, info);
+        pos.undo_move(m);
+
+        if e >= rbeta {
+            return false;
+        }
+    }
+
+    true
+}
+
+// `ply` is how far below the root `pos` is, for scoring mates
+pub(crate) fn quiescence(
+    pos: &mut Position, alpha: i32, beta: i32, ply: usize, options: &SearchOptions
+) -> i32 {
+    quiescence_with_depth(pos, alpha, beta, ply, options).0
+}
+
+// Also reports how many plies below `pos` the deepest node searched was
+pub(crate) fn quiescence_with_depth(
+    pos: &mut Position, alpha: i32, beta: i32, ply: usize, options: &SearchOptions
+) -> (i32, usize) {
+    let mut deepest = 0;
+    let score = quiescence_internal::<true>(pos, alpha, beta, ply, 0, &mut deepest, options);
+    (score, deepest)
+}
+
+fn quiescence_internal<const FIRST: bool>(
+    pos: &mut Position, alpha: i32, beta: i32, ply: usize, qdepth: usize, deepest: &mut usize,
+    options: &SearchOptions
+) -> i32 {
+    diagnostics::record_quiesce_depth(qdepth);
+    *deepest = (*deepest).max(qdepth);
+    // Long capture chains would otherwise recurse without bound
+    if qdepth >= MAX_QUIESCE_DEPTH {
+        return eval_at(pos, ply, &options.weights);
+    }
+
+    let in_check = pos.in_check();
+    let mut alpha = alpha;
+    // Fail-soft like alpha_beta, the best score is returned even outside the window
+    let mut best_score = MIN_RAT;
+
+    // Standing pat is unsound in check, every evasion must be tried
+    if !in_check {
+        let stand_pat = eval_at(pos, ply, &options.weights);
+
+        if stand_pat >= beta {
+            diagnostics::add_beta_cutoffs();
+            return stand_pat;
+        }
+
+        best_score = stand_pat;
+        if stand_pat > alpha {
+            alpha = stand_pat;
+        }
     }
 
     let mut move_list = MoveList::new();
-    let gt = if pos.in_check() {
+    let gt = if in_check {
         GenType::Evasions
     } else {
         GenType::Captures
     };
-    generate_for(pos, &mut move_list, pos.to_move(), gt);
+    generate_for_legal(pos, gt, &mut move_list);
+    if FIRST && !in_check && options.quiesce_checks {
+        generate_for_legal(pos, GenType::QuietChecks, &mut move_list);
+    }
 
+    moveorder::score_moves(pos, &mut move_list);
     for i in 0..move_list.len() {
-        let m = move_list.get(i);
-        if !pos.is_legal(m) {
-            continue;
-        }
+        let m = move_list.pick_best(i);
         pos.do_move(m);
-        let e = -quiescence(pos, -beta, -alpha);
+        let e = -quiescence_internal::<false>(pos, -beta, -alpha, ply + 1, qdepth + 1, deepest,
+                                              options);
         pos.undo_move(m);
 
         if e >= beta {
             diagnostics::add_beta_cutoffs();
-            return beta;
+            return e;
         }
 
+        best_score = best_score.max(e);
         if e > alpha {
             alpha = e;
         }
     }
 
-    alpha
+    if in_check && move_list.is_empty() {
+        return mated_in(ply);
+    }
+
+    best_score
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::individual_squares::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn late_move_pruning_keeps_best_move() {
+        crate::init_for_tests();
+        let fens = [
+            "4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1",
+            "4k3/8/8/8/8/2n5/8/R3K3 w - - 0 1",
+        ];
+
+        for fen in fens {
+            let mut best = [Move::NULL; 2];
+            for (i, late_move_pruning) in [false, true].into_iter().enumerate() {
+                let options = SearchOptions { late_move_pruning, ..SearchOptions::DEFAULT };
+                let mut p = Position::from_str(fen).unwrap();
+                alpha_beta_with(&mut p, &mut best[i], 3, &options);
+            }
+            assert_eq!(best[0], best[1], "{fen}");
+        }
+    }
+
+    #[test]
+    fn knight_outpost_on_d5() {
+        crate::init_for_tests();
+        let outpost = Position::from_str("4k3/p6p/8/3N4/4P3/8/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(knight_outposts(&outpost, Color::White), KNIGHT_OUTPOST);
+
+        // ...c6 would kick the knight
+        let chased = Position::from_str("4k3/p1p4p/8/3N4/4P3/8/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(knight_outposts(&chased, Color::White), 0);
+
+        let unguarded = Position::from_str("4k3/p6p/8/3N4/8/4P3/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(knight_outposts(&unguarded, Color::White), 0);
+    }
+
+    #[test]
+    fn connected_passers_beat_split_ones() {
+        crate::init_for_tests();
+        let score = |fen| passed_pawn_score(&Position::from_str(fen).unwrap());
+        let connected = score("7k/8/8/3PP3/8/8/8/K7 w - - 0 1");
+        let split = score("7k/8/8/1P4P1/8/8/8/K7 w - - 0 1");
+        assert_eq!(split, 2 * PASSED_PAWN[4]);
+        assert_eq!(connected, split + 2 * CONNECTED_PASSER);
+        // The same from Black's side
+        assert_eq!(score("k7/8/8/8/3pp3/8/8/7K b - - 0 1"), -connected);
+
+        // d5 guards e6
+        assert_eq!(
+            score("7k/8/4P3/3P4/8/8/8/K7 w - - 0 1"),
+            PASSED_PAWN[5] + PASSED_PAWN[4] + 2 * CONNECTED_PASSER + PROTECTED_PASSER
+        );
+        // d5 and e6 stop each other passing
+        assert_eq!(score("7k/8/4p3/3P4/8/8/8/K7 w - - 0 1"), 0);
+
+        // The black king sits on the e5 pawn's stop square
+        assert_eq!(
+            score("8/8/4k3/4P3/8/8/8/K7 w - - 0 1"),
+            PASSED_PAWN[4] - BLOCKADED_PASSER
+        );
+    }
+
+    #[test]
+    fn bishop_blocked_by_own_pawns() {
+        crate::init_for_tests();
+        // Dark-squared bishop with pawns on c3, d4 (dark) and e4 (light)
+        let p = Position::from_str("4k3/8/8/8/3PP3/2P5/8/2B1K3 w - - 0 1").unwrap();
+        assert_eq!(bad_bishops(&p, Color::White), -2 * BAD_BISHOP_PAWN);
+    }
+
+    #[test]
+    fn fail_soft_bounds_are_tight() {
+        crate::init_for_tests();
+        let fen = "4k3/8/2n5/8/4b3/8/3R4/4K3 w - - 0 1";
+        let mut p = Position::from_str(fen).unwrap();
+        let mut best = Move::NULL;
+        let exact = alpha_beta(&mut p, &mut best, 1);
+        assert!(best.is_ok());
+
+        // Depth 1 from a PV node has no pruning, so bounds are exact limits
+        let window = |alpha, beta| Node {
+            depth: 1,
+            ply: 0,
+            alpha,
+            beta,
+            pv_node: true,
+            extended: false,
+            prev: Move::NULL,
+        };
+        let mut info = AbInfo::new(Move::NULL, SearchOptions::DEFAULT);
+        let high =
+            alpha_beta_internal::<false>(&mut p, window(exact - 200, exact - 100), &mut info);
+        assert!(high >= exact - 100 && high <= exact, "{high} vs {exact}");
+        let entry = tt::probe(p.state().key()).unwrap();
+        assert_eq!((entry.bound, entry.score), (Bound::Lower, high));
+
+        let low = alpha_beta_internal::<false>(&mut p, window(exact + 100, exact + 200), &mut info);
+        assert!(low >= exact && low <= exact + 100, "{low} vs {exact}");
+        let entry = tt::probe(p.state().key()).unwrap();
+        assert_eq!((entry.bound, entry.score), (Bound::Upper, low));
+    }
+
+    #[test]
+    fn black_root_finds_capture() {
+        crate::init_for_tests();
+        let mut p = Position::from_str("4k3/8/8/3q4/8/8/3R4/K7 b - - 0 1").unwrap();
+        let mut best = Move::NULL;
+        alpha_beta(&mut p, &mut best, 2);
+        assert_eq!(best, Move::new(D5, D2));
+    }
+
+    #[test]
+    fn tempo_favours_side_to_move() {
+        crate::init_for_tests();
+        let w = Position::from_str("4k3/pp6/8/8/8/8/5PPP/4K2R w - - 0 1").unwrap();
+        let b = Position::from_str("4k3/pp6/8/8/8/8/5PPP/4K2R b - - 0 1").unwrap();
+        assert_eq!(static_evaluate(&w) - static_evaluate(&b), 2 * TEMPO);
+    }
+
+    #[test]
+    fn search_stores_tt_move() {
+        crate::init_for_tests();
+        let mut p = Position::from_str("3k4/8/2n5/8/4P3/8/3B4/4K3 w - - 0 1").unwrap();
+        let mut best = Move::NULL;
+        alpha_beta(&mut p, &mut best, 3);
+
+        assert!(best.is_ok());
+        assert_eq!(p.tt_move(), Some(best));
+    }
+
+    #[test]
+    fn singular_extension_keeps_only_move() {
+        crate::init_for_tests();
+        // Rxa1 is the only move that avoids Rxe1 mate
+        let fen = "6k1/5ppp/8/8/8/8/5PPP/r3R1K1 w - - 0 1";
+        let search = |depth, singular_extensions| {
+            let options = SearchOptions { singular_extensions, ..SearchOptions::DEFAULT };
+            let mut p = Position::from_str(fen).unwrap();
+            let mut best = Move::NULL;
+            let score = alpha_beta_with(&mut p, &mut best, depth, &options);
+            (best, score)
+        };
+
+        let (plain, plain_score) = search(4, false);
+        let (extended, extended_score) = search(4, true);
+        assert_eq!(plain, extended);
+        assert_eq!(extended.to_string(), "e1a1");
+        // The forced recapture is searched a ply deeper, as a depth 5 search would
+        assert_ne!(extended_score, plain_score);
+        assert_eq!(extended_score, search(5, false).1);
+    }
+
+    #[test]
+    fn rook_prefers_open_file() {
+        crate::init_for_tests();
+        let behind_pawn = Position::from_str("4k3/8/8/8/8/8/P7/R3K3 w - - 0 1").unwrap();
+        let open_file = Position::from_str("4k3/8/8/8/8/8/P7/1R2K3 w - - 0 1").unwrap();
+        let seventh = Position::from_str("4k3/1R6/8/8/8/8/P7/4K3 w - - 0 1").unwrap();
+
+        assert!(static_evaluate(&open_file) > static_evaluate(&behind_pawn));
+        assert!(static_evaluate(&seventh) > static_evaluate(&open_file));
+    }
+
+    #[test]
+    fn trace_sums_to_static_eval() {
+        crate::init_for_tests();
+        for fen in [
+            Position::STARTPOS,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "4k3/1R6/8/8/8/8/P7/4K3 w - - 0 1",
+        ] {
+            let p = Position::from_str(fen).unwrap();
+            let t = trace(&p);
+            assert_eq!(t.total(), static_evaluate(&p), "{t}");
+        }
+    }
+
+    #[test]
+    fn quiescence_fails_soft() {
+        crate::init_for_tests();
+        let mut p = Position::from_str("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1").unwrap();
+        let options = SearchOptions::DEFAULT;
+        let exact = quiescence(&mut p, MIN_RAT, MAX_RAT, 0, &options);
+        assert!(exact > 400);
+
+        // Rxd5 is found either way, and its score comes back rather than the bound
+        assert_eq!(quiescence(&mut p, exact - 300, exact - 200, 0, &options), exact);
+        assert_eq!(quiescence(&mut p, exact + 200, exact + 300, 0, &options), exact);
+    }
+
+    #[test]
+    fn quiescence_depth_is_capped() {
+        crate::init_for_tests();
+        let mut p = Position::from_str("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1").unwrap();
+        let stand_pat = p.to_move().persp(static_evaluate(&p));
+
+        // Out of plies the hanging queen is not taken any more
+        let options = SearchOptions::DEFAULT;
+        let mut q = |qdepth| {
+            quiescence_internal::<false>(&mut p, MIN_RAT, MAX_RAT, 0, qdepth, &mut 0, &options)
+        };
+        let capped = q(MAX_QUIESCE_DEPTH);
+        assert_eq!(capped, stand_pat);
+        let last = q(MAX_QUIESCE_DEPTH - 1);
+        assert!(last > stand_pat + 500);
+
+        // Every file is a chain of queen and rook trades
+        let mut p = Position::from_str("rrrrkrrr/qqqqqqqq/8/8/8/8/QQQQQQQQ/RRRRKRRR w - - 0 1")
+            .unwrap();
+        let fen = p.fen();
+        let (e, deepest) = quiescence_with_depth(&mut p, MIN_RAT, MAX_RAT, 0, &options);
+        assert!(!Score(e).is_mate());
+        assert_eq!(deepest, MAX_QUIESCE_DEPTH);
+        assert_eq!(p.fen(), fen);
+    }
+
+    #[test]
+    fn full_eval_is_symmetric() {
+        crate::init_for_tests();
+        for fen in [
+            Position::STARTPOS,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r1bq1rk1/pp2bppp/2n1pn2/3p4/2PP4/2N1PN2/PP2BPPP/R2QKB1R b KQ - 0 8",
+        ] {
+            let p = Position::from_str(fen).unwrap();
+            assert_eq!(static_evaluate(&p), -static_evaluate(&p.flipped()), "{fen}");
+        }
+        assert!(static_evaluate(&Position::startpos()).abs() <= TEMPO);
+    }
+
+    #[test]
+    fn king_terms_follow_the_phase() {
+        crate::init_for_tests();
+        let full = Position::startpos();
+        assert_eq!(game_phase(&full), MAX_PHASE);
+        let bare = Position::from_str("4k3/pppp4/8/8/8/8/4PPPP/4K3 w - - 0 1").unwrap();
+        assert_eq!(game_phase(&bare), 0);
+
+        // Walking the king up the board costs with the queens on, pays without
+        let centre = |fen| {
+            let p = Position::from_str(fen).unwrap();
+            static_evaluate(&p)
+        };
+        assert!(
+            centre("rnbqkbnr/pppppppp/8/8/4K3/8/PPPPPPPP/RNBQ1R2 w kq - 0 1")
+                < centre("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQ1RK1 w kq - 0 1")
+        );
+        assert!(centre("4k3/8/8/8/4K3/8/8/8 w - - 0 1") > centre("4k3/8/8/8/8/8/8/6K1 w - - 0 1"));
+    }
+
+    #[test]
+    fn quiescence_finds_checking_fork() {
+        crate::init_for_tests();
+        // Nc7+ forks the king and queen, which captures alone can't see
+        let mut p = Position::from_str("q3k3/8/8/1N6/8/8/8/4K3 w - - 0 1").unwrap();
+
+        let captures_only = SearchOptions { quiesce_checks: false, ..SearchOptions::DEFAULT };
+        let without = quiescence(&mut p, MIN_RAT, MAX_RAT, 0, &captures_only);
+        let with = quiescence(&mut p, MIN_RAT, MAX_RAT, 0, &SearchOptions::DEFAULT);
+
+        assert_eq!(without, -600 + TEMPO);
+        // A knight up once the king has stepped out of the fork, less 13 for
+        // how the kings stand at the end of the line
+        assert_eq!(with, 300 - TEMPO - 13);
+
+        // With only material and tempo weighed, exactly the knight
+        let mut terms = [(0, 0); EVAL_TERMS.len()];
+        terms[0] = (100, 100);
+        terms[EVAL_TERMS.len() - 1] = (100, 100);
+        let material_only = SearchOptions { weights: Weights { terms }, ..SearchOptions::DEFAULT };
+        assert_eq!(quiescence(&mut p, MIN_RAT, MAX_RAT, 0, &material_only), 300 - TEMPO);
+    }
+
+    #[test]
+    fn hanging_queen_is_a_threat() {
+        crate::init_for_tests();
+        // The knight hits the queen, and nothing defends her
+        let p = Position::from_str("4k3/8/3q4/8/4N3/8/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(threats(&p), 900 / THREAT_DIVISOR);
+
+        // Defended pieces attacked by equal or bigger ones are no threat
+        let p = Position::from_str("4k3/4r3/8/8/8/8/4R3/4K3 w - - 0 1").unwrap();
+        assert_eq!(threats(&p), 0);
+        let p = Position::from_str("4k3/3p4/4p3/8/8/8/4Q3/4K3 w - - 0 1").unwrap();
+        assert_eq!(threats(&p), 0);
+    }
+
+    #[test]
+    fn score_bounds_negate() {
+        assert_eq!(MAX_RAT.checked_neg(), Some(MIN_RAT));
+        assert_eq!(MIN_RAT.checked_neg(), Some(MAX_RAT));
+        const { assert!(MATE < MAX_RAT && -MATE > MIN_RAT) };
+
+        assert!(Score(-MATE).is_mate() && !Score(950).is_mate());
+        assert_eq!(Score(MATE - 3).mate_in(), Some(2));
+        assert_eq!(Score(-MATE + 2).to_string(), "mate -1");
+        assert_eq!(Score(35).to_string(), "cp 35");
+        assert_eq!(Score(MATE).to_cp(), MATE_IN_MAX_PLY);
+    }
+
+    #[test]
+    fn minimax_through_mate_does_not_overflow() {
+        crate::init_for_tests();
+        // Ra8# is mate in one, so deeper plies walk into mated positions
+        let mut p = Position::from_str("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        let mut m = Move::NULL;
+        assert_eq!(minimax::<true>(&mut p, &mut m, 3), MATE - 1);
+        assert_eq!(m, Move::new(A1, A8));
+    }
+
+    #[test]
+    fn mated_side_scores_negative_mate() {
+        crate::init_for_tests();
+        // Back rank mates, once for each side to move
+        for fen in ["6rk/8/8/8/8/8/5PPP/r5K1 w - - 0 1", "R5k1/5ppp/8/8/8/8/8/6RK b - - 0 1"] {
+            let mut p = Position::from_str(fen).unwrap();
+            let mut m = Move::NULL;
+            assert_eq!(alpha_beta(&mut p, &mut m, 2), -MATE, "{fen}");
+        }
+
+        let mut m = Move::NULL;
+        let mut p = Position::from_str("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        assert_eq!(alpha_beta(&mut p, &mut m, 3), MATE - 1);
+        let mut p = Position::from_str("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+        assert_eq!(alpha_beta(&mut p, &mut m, 3), DRAW);
+    }
 }
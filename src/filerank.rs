@@ -49,6 +49,11 @@ impl File {
     pub const fn as_mask(self) -> u64 {
         0x0101010101010101 << self as u8
     }
+
+    // Mirrored across the middle of the board, a <-> h
+    pub const fn flip(self) -> Self {
+        unsafe { std::mem::transmute(7 - self as u8) }
+    }
 }
 
 impl Rank {
@@ -56,7 +61,8 @@ impl Rank {
         0xFF << (8 * self as u8)
     }
 
-    pub fn relative(self, color: Color) -> Self {
+    // Ranks are 0..=7, so 7 - rank can't underflow
+    pub const fn relative(self, color: Color) -> Self {
         match color {
             Color::White => self,
             Color::Black => unsafe { std::mem::transmute(7 - self as u8) },
@@ -121,3 +127,31 @@ impl Add<Rank> for File {
         Square::create(self, rhs)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_ranks_for_both_colors() {
+        use Rank::*;
+        let ranks = [One, Two, Three, Four, Five, Six, Seven, Eight];
+        for (i, &r) in ranks.iter().enumerate() {
+            assert_eq!(r.relative(Color::White), r);
+            assert_eq!(r.relative(Color::Black), ranks[7 - i]);
+            assert_eq!(r.relative(Color::Black).relative(Color::Black), r);
+        }
+        assert_eq!(Five.relative(Color::Black), Four);
+    }
+
+    #[test]
+    fn flipped_files() {
+        use File::*;
+        assert_eq!(A.flip(), H);
+        assert_eq!(D.flip(), E);
+        for f in [A, B, C, D, E, F, G, H] {
+            assert_eq!(f.flip().flip(), f);
+            assert_ne!(f.flip(), f);
+        }
+    }
+}
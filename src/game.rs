@@ -0,0 +1,146 @@
+/*
+    ChessLib, a UCI chess engine
+    Copyright (C) 2023 Sam Price
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use crate::prelude::*;
+
+// A game being played: the position plus the moves that led to it, so
+// moves can be taken back
+#[derive(Debug, Clone)]
+pub struct Game {
+    pos: Position,
+    moves: Vec<Move>,
+}
+
+impl Game {
+    pub fn new(start: Position) -> Self {
+        Self {
+            pos: start,
+            moves: Vec::new(),
+        }
+    }
+
+    // Illegal moves are refused and leave the game as it was
+    pub fn push(&mut self, m: Move) -> Result<(), &'static str> {
+        if !self.pos.is_legal_checked(m) {
+            return Err("Illegal move");
+        }
+        self.pos.do_move(m);
+        self.moves.push(m);
+        Ok(())
+    }
+
+    // Takes back the last move, None at the starting position
+    pub fn pop(&mut self) -> Option<Move> {
+        let m = self.moves.pop()?;
+        self.pos.undo_move(m);
+        Some(m)
+    }
+
+    #[inline]
+    pub fn position(&self) -> &Position {
+        &self.pos
+    }
+    #[inline]
+    pub fn moves(&self) -> &[Move] {
+        &self.moves
+    }
+
+    pub fn result(&self) -> GameResult {
+        self.pos.status_with_moves(&mut MoveList::new())
+    }
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Self::new(Position::startpos())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Game;
+    use crate::prelude::{individual_squares::*, GameResult, Move, Position};
+
+    #[test]
+    fn takeback_matches_replay() {
+        crate::init_for_tests();
+        let moves = [
+            Move::new(E2, E4),
+            Move::new(E7, E5),
+            Move::new(G1, F3),
+            Move::new(B8, C6),
+            Move::new(F1, C4),
+        ];
+        let mut game = Game::default();
+        for m in moves {
+            game.push(m).unwrap();
+        }
+        assert_eq!(game.pop(), Some(Move::new(F1, C4)));
+        assert_eq!(game.pop(), Some(Move::new(B8, C6)));
+        assert_eq!(game.moves(), &moves[..3]);
+
+        let mut replay = Game::default();
+        for &m in &moves[..3] {
+            replay.push(m).unwrap();
+        }
+        assert_eq!(game.position().fen(), replay.position().fen());
+        assert_eq!(
+            game.position().state().key(),
+            replay.position().state().key()
+        );
+
+        assert!(game.push(Move::new(E5, E4)).is_err());
+        assert_eq!(game.moves().len(), 3);
+        while game.pop().is_some() {}
+        assert_eq!(game.position().fen(), Position::startpos().fen());
+    }
+
+    #[test]
+    fn clone_then_takeback() {
+        crate::init_for_tests();
+        let mut game = Game::default();
+        game.push(Move::new(E2, E4)).unwrap();
+        game.push(Move::new(E7, E5)).unwrap();
+        let mut copy = game.clone();
+
+        assert_eq!(copy.pop(), Some(Move::new(E7, E5)));
+        assert_eq!(copy.pop(), Some(Move::new(E2, E4)));
+        assert_eq!(copy.position().fen(), Position::startpos().fen());
+
+        // The original still has both moves and can take them back too
+        assert_eq!(game.moves().len(), 2);
+        assert_eq!(game.pop(), Some(Move::new(E7, E5)));
+        copy.push(Move::new(E2, E4)).unwrap();
+        assert_eq!(game.position().fen(), copy.position().fen());
+        assert_eq!(game.position().state().key(), copy.position().state().key());
+    }
+
+    #[test]
+    fn result_after_fools_mate() {
+        crate::init_for_tests();
+        let mut game = Game::default();
+        for m in [Move::new(F2, F3), Move::new(E7, E5), Move::new(G2, G4)] {
+            game.push(m).unwrap();
+            assert_eq!(game.result(), GameResult::Ongoing);
+        }
+        game.push(Move::new(D8, H4)).unwrap();
+        assert_eq!(game.result(), GameResult::Checkmate);
+        game.pop();
+        assert_eq!(game.result(), GameResult::Ongoing);
+    }
+}
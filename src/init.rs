@@ -27,6 +27,9 @@ static mut KING_ATTACKS: [Bitboard; 64] = Bitboard::arr::<64>();
 static mut PAWN_ATTACKS: [[Bitboard; 2]; 64] = Bitboard::arr_2d::<2, 64>();
 static mut BETWEEN_SQUARES: [[Bitboard; 64]; 64] = Bitboard::arr_2d::<64, 64>();
 static mut LINE_BB: [[Bitboard; 64]; 64] = Bitboard::arr_2d::<64, 64>();
+// Slider attacks on an empty board
+static mut PSEUDO_ROOK_ATTACKS: [Bitboard; 64] = Bitboard::arr::<64>();
+static mut PSEUDO_BISHOP_ATTACKS: [Bitboard; 64] = Bitboard::arr::<64>();
 
 pub fn init() {
     if unsafe { PAWN_ATTACKS[0][0] } != Bitboard::ZERO {
@@ -35,6 +38,7 @@ pub fn init() {
     init_pawn_attacks();
     init_knight_attacks();
     init_king_attacks();
+    init_pseudo_slider_attacks();
     // Required first. Because between() uses Square::in_line in the setup
     init_between_and_board_lines();
 }
@@ -48,6 +52,12 @@ pub fn king_attack(square: Square) -> Bitboard {
 pub fn pawn_attack(square: Square, color: Color) -> Bitboard {
     unsafe { PAWN_ATTACKS[square.inner() as usize][color as usize] }
 }
+pub fn pseudo_rook_attack(square: Square) -> Bitboard {
+    unsafe { PSEUDO_ROOK_ATTACKS[square.inner() as usize] }
+}
+pub fn pseudo_bishop_attack(square: Square) -> Bitboard {
+    unsafe { PSEUDO_BISHOP_ATTACKS[square.inner() as usize] }
+}
 pub fn between<const INCLUDE_ENDPOINT: bool>(s1: Square, s2: Square) -> Bitboard {
     if INCLUDE_ENDPOINT {
         unsafe { BETWEEN_SQUARES[s1.inner() as usize][s2.inner() as usize] | s2 }
@@ -101,6 +111,12 @@ fn init_king_attacks() {
         }
     });
 }
+fn init_pseudo_slider_attacks() {
+    Bitboard::MAX.map_by_square(|square| unsafe {
+        PSEUDO_ROOK_ATTACKS[square.inner() as usize] = rook_moves(square, Bitboard::ZERO);
+        PSEUDO_BISHOP_ATTACKS[square.inner() as usize] = bishop_moves(square, Bitboard::ZERO);
+    });
+}
 fn init_between_and_board_lines() {
     for i in 0..64 {
         for j in 0..64 {
@@ -111,15 +127,15 @@ fn init_between_and_board_lines() {
                 continue;
             }
 
-            let rook_si = rook_moves(si, Bitboard::ZERO);
-            let bish_si = bishop_moves(si, Bitboard::ZERO);
+            let rook_si = pseudo_rook_attack(si);
+            let bish_si = pseudo_bishop_attack(si);
             let b_rook_si = rook_moves(si, Bitboard::from(sj));
             let b_bish_si = bishop_moves(si, Bitboard::from(sj));
 
             let line = if (rook_si & sj).nonzero() {
-                rook_si & rook_moves(sj, Bitboard::ZERO)
+                rook_si & pseudo_rook_attack(sj)
             } else if (bish_si & sj).nonzero() {
-                bish_si & bishop_moves(sj, Bitboard::ZERO)
+                bish_si & pseudo_bishop_attack(sj)
             } else {
                 Bitboard::ZERO
             };
@@ -142,3 +158,17 @@ fn init_between_and_board_lines() {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pseudo_attacks_match_empty_board_magics() {
+        crate::init_for_tests();
+        Bitboard::MAX.map_by_square(|s| {
+            assert_eq!(pseudo_rook_attack(s), rook_moves(s, Bitboard::ZERO));
+            assert_eq!(pseudo_bishop_attack(s), bishop_moves(s, Bitboard::ZERO));
+        });
+    }
+}
@@ -15,12 +15,15 @@
     You should have received a copy of the GNU General Public License
     along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
+pub mod accumulator;
 mod bitboard;
 mod chessmove;
 mod color;
+mod datagen;
 pub mod diagnostics;
 pub mod evaluate;
 mod filerank;
+mod game;
 mod init;
 mod magic;
 mod movegen;
@@ -31,19 +34,25 @@ mod prng;
 pub mod search;
 mod square;
 mod thread;
-mod tt;
+pub mod time;
+pub mod tt;
 pub mod zobrist;
 
 pub mod prelude {
+    pub use crate::accumulator::AccumulatorListener;
     pub use crate::bitboard::*;
     pub use crate::chessmove::*;
     pub use crate::color::Color;
+    pub use crate::datagen::Adjudication;
     pub use crate::filerank::*;
+    pub use crate::game::Game;
     pub use crate::init::{between, line, king_attack, pawn_attack, knight_attack};
+    pub use crate::init::{pseudo_bishop_attack, pseudo_rook_attack};
     pub use crate::magic::{bishop_moves, queen_moves, rook_moves};
     pub use crate::movegen::*;
     pub use crate::piece::*;
     pub use crate::position::*;
+    pub use crate::prng::Prng;
     pub use crate::square::*;
     pub use crate::zobrist::{Key, self};
 }
@@ -54,6 +63,14 @@ pub fn initialize() {
     unsafe {
         zobrist::init_zobrist();
     }
+    tt::resize(tt::DEFAULT_TT_MB);
+}
+
+// Tests run on several threads, so the tables must only be built once
+#[cfg(test)]
+pub(crate) fn init_for_tests() {
+    static INIT: std::sync::Once = std::sync::Once::new();
+    INIT.call_once(initialize);
 }
 
 // If we want to use PEXT instructions
@@ -93,7 +93,6 @@ fn init_magics<const IS_ROOK: bool>(
     magic_table: &'static mut [Magic],
 ) {
     let mut sz = 0;
-    let mut b: Bitboard;
     let mut occ: [Bitboard; 4096] = [Bitboard::ZERO; 4096];
     let mut refer: [Bitboard; 4096] = [Bitboard::ZERO; 4096];
 
@@ -121,10 +120,9 @@ fn init_magics<const IS_ROOK: bool>(
         m.shift = max.abs_diff(m.mask.popcnt() as u32);
         m.ptr = ptr;
 
-        b = Bitboard::ZERO;
         sz = 0;
 
-        loop {
+        for b in Bitboard::new(m.mask).subsets() {
             occ[sz] = b;
             refer[sz] = slider_attack::<IS_ROOK>(s, b);
 
@@ -133,11 +131,6 @@ fn init_magics<const IS_ROOK: bool>(
             }
 
             sz += 1;
-            b = Bitboard::new(b.inner().wrapping_sub(m.mask) & m.mask);
-
-            if b.zero() {
-                break;
-            }
         }
 
         if USE_PEXT {
@@ -6,10 +6,6 @@ use chess_lib::prelude::*;
 use chess_lib::debug;
 use chess_lib::search;
 
-// FIXME Position::do_move is slow
-// FIXME Position::attacks_to_occ may be slow
-// FIXME Position::compute_state and Position::generate_all are possibly slow
-
 fn main() {
     chess_lib::initialize();
     debug!("Done with initialization");
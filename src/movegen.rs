@@ -20,11 +20,13 @@ use crate::bitboard::Bitboard;
 use crate::chessmove::{MType, Move};
 use crate::color::Color;
 use crate::filerank::{File, Rank};
-use crate::init::{between, king_attack, knight_attack, pawn_attack};
-use crate::magic::{bishop_moves, queen_moves, rook_moves};
+use crate::init::{between, king_attack, knight_attack, line, pawn_attack};
+use crate::init::{pseudo_bishop_attack, pseudo_rook_attack};
+use crate::magic::{bishop_moves, rook_moves};
+use crate::moveorder::is_quiet;
 use crate::piece::PType::{self, *};
 use crate::position::Position;
-use crate::square::{individual_squares::*, Square};
+use crate::square::Square;
 
 use crate::debug;
 
@@ -40,6 +42,8 @@ pub enum GenType {
 #[derive(Debug, Clone)]
 pub struct MoveList {
     moves: [Move; 256],
+    // Ordering scores, only meaningful once filled in by the caller
+    scores: [i32; 256],
     index: usize,
 }
 
@@ -48,6 +52,7 @@ impl MoveList {
     pub const fn new() -> Self {
         Self {
             moves: [Move::NULL; 256],
+            scores: [0; 256],
             index: 0,
         }
     }
@@ -87,17 +92,216 @@ impl MoveList {
     }
     fn swap(&mut self, a: usize, b: usize) {
         debug_assert!(a < self.len() && b < self.len());
-        let mut a_ptr = &mut self.moves[a] as *mut Move;
-        let mut b_ptr = &mut self.moves[b] as *mut Move;
-        unsafe {
-            std::ptr::swap(&mut a_ptr, &mut b_ptr);
+        self.moves.swap(a, b);
+        self.scores.swap(a, b);
+    }
+
+    #[inline(always)]
+    pub fn set_score(&mut self, index: usize, score: i32) {
+        debug_assert!(index < self.index);
+        self.scores[index] = score;
+    }
+
+    #[inline(always)]
+    pub const fn score(&self, index: usize) -> i32 {
+        debug_assert!(index < self.len());
+        self.scores[index]
+    }
+
+    // Selection step: moves the best-scored move at or after `from` into
+    // `from` and returns it, so only the moves actually tried get sorted
+    pub fn pick_best(&mut self, from: usize) -> Move {
+        debug_assert!(from < self.len());
+        let mut best = from;
+        for i in from + 1..self.index {
+            if self.scores[i] > self.scores[best] {
+                best = i;
+            }
+        }
+        self.swap(from, best);
+        self.moves[from]
+    }
+
+    // Moves `m` to the front, shifting the moves before it back by one.
+    // False if `m` isn't in the list.
+    pub fn move_to_front(&mut self, m: Move) -> bool {
+        match self.as_slice().iter().position(|&x| x == m) {
+            Some(i) => {
+                self.moves[..=i].rotate_right(1);
+                self.scores[..=i].rotate_right(1);
+                true
+            }
+            None => false,
         }
     }
 
+    // Removes repeated moves, keeping the first occurrence of each
+    pub fn dedup(&mut self) {
+        let mut len = 0;
+        for i in 0..self.index {
+            let m = self.moves[i];
+            if !self.moves[..len].contains(&m) {
+                self.moves[len] = m;
+                self.scores[len] = self.scores[i];
+                len += 1;
+            }
+        }
+        self.index = len;
+    }
+
+    // Keeps only the moves `keep` accepts, in their original order
+    pub fn retain(&mut self, mut keep: impl FnMut(Move) -> bool) {
+        let mut len = 0;
+        for i in 0..self.index {
+            if keep(self.moves[i]) {
+                self.moves[len] = self.moves[i];
+                self.scores[len] = self.scores[i];
+                len += 1;
+            }
+        }
+        self.index = len;
+    }
+
+    #[cfg(debug_assertions)]
+    pub fn assert_no_duplicates(&self) {
+        for i in 0..self.index {
+            assert!(
+                !self.moves[..i].contains(&self.moves[i]),
+                "Duplicate move generated: {}",
+                self.moves[i]
+            );
+        }
+    }
+
+    // The old scores don't belong to the new moves, so they are reset
     pub fn replace(&mut self, moves: Vec<Move>) {
-        unsafe {
-            std::ptr::copy(&moves.as_slice() as *const _, &mut self.moves.as_slice() as *mut _, 256);
+        debug_assert!(moves.len() <= 256);
+        self.moves[..moves.len()].copy_from_slice(&moves);
+        self.scores[..moves.len()].fill(0);
+        self.index = moves.len();
+    }
+
+    #[inline]
+    pub fn as_slice(&self) -> &[Move] {
+        &self.moves[..self.index]
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MoveListIter {
+    list: MoveList,
+    next: usize,
+}
+
+impl Iterator for MoveListIter {
+    type Item = Move;
+    fn next(&mut self) -> Option<Move> {
+        if self.next < self.list.len() {
+            self.next += 1;
+            Some(self.list.moves[self.next - 1])
+        } else {
+            None
+        }
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = self.list.len() - self.next;
+        (n, Some(n))
+    }
+}
+
+impl ExactSizeIterator for MoveListIter {}
+
+impl IntoIterator for MoveList {
+    type Item = Move;
+    type IntoIter = MoveListIter;
+    fn into_iter(self) -> MoveListIter {
+        MoveListIter { list: self, next: 0 }
+    }
+}
+
+impl Position {
+    pub fn legal_moves(&self) -> MoveList {
+        let mut list = MoveList::new();
+        generate_legal::<true>(self, &mut list);
+        list
+    }
+
+    // Every legal way out of check, empty when mated. Only for positions
+    // in check, otherwise use legal_moves.
+    pub fn legal_evasions(&self) -> MoveList {
+        assert!(self.in_check(), "legal_evasions needs a position in check");
+        let mut list = MoveList::new();
+        generate_for_legal(self, GenType::Evasions, &mut list);
+        list
+    }
+
+    // Legal moves of type `gt`, whatever the check status. In check only
+    // evasions are generated, keeping those that fit `gt`: the captures and
+    // promotions for Captures, the rest for Quiet and QuietChecks.
+    pub fn generate(&self, gt: GenType) -> MoveList {
+        let mut list = MoveList::new();
+        if !self.in_check() {
+            let gt = if gt == GenType::Evasions {
+                GenType::NonEvasions
+            } else {
+                gt
+            };
+            generate_for_legal(self, gt, &mut list);
+            return list;
+        }
+
+        generate_for_legal(self, GenType::Evasions, &mut list);
+        match gt {
+            GenType::Captures => list.retain(|m| !is_quiet(self, m)),
+            GenType::Quiet => list.retain(|m| is_quiet(self, m)),
+            GenType::QuietChecks => list.retain(|m| is_quiet(self, m) && self.gives_check(m)),
+            GenType::Evasions | GenType::NonEvasions => (),
         }
+        list
+    }
+
+    // The legal moves are generated once up front, then handed out one by one
+    #[inline]
+    pub fn legal_move_iter(&self) -> MoveListIter {
+        self.legal_moves().into_iter()
+    }
+}
+
+// `for m in &pos` walks the legal moves
+impl IntoIterator for &Position {
+    type Item = Move;
+    type IntoIter = MoveListIter;
+    fn into_iter(self) -> MoveListIter {
+        self.legal_move_iter()
+    }
+}
+
+// One rank towards the enemy side
+#[inline(always)]
+fn forward(b: Bitboard, us: Color) -> Bitboard {
+    match us {
+        Color::White => b << 8,
+        Color::Black => b >> 8,
+    }
+}
+
+// Pushes every move landing on `targets`, made from `offset` squares back
+#[inline(always)]
+fn push_pawn_moves(list: &mut MoveList, targets: Bitboard, offset: i8) {
+    for to in targets {
+        let from = unsafe { Square::new((to.inner() as i8 - offset) as u8) };
+        list.push(Move::new(from, to));
+    }
+}
+
+#[inline(always)]
+fn push_promotions(list: &mut MoveList, targets: Bitboard, offset: i8) {
+    for to in targets {
+        let from = unsafe { Square::new((to.inner() as i8 - offset) as u8) };
+        list.push(Move::promotion(from, to, Knight));
+        list.push(Move::promotion(from, to, Bishop));
+        list.push(Move::promotion(from, to, Rook));
+        list.push(Move::promotion(from, to, Queen));
     }
 }
 
@@ -107,6 +311,7 @@ fn generate_pawn_moves(
     us: Color,
     gt: GenType,
     target: Bitboard,
+    restrict: Bitboard,
 ) {
     let r3bb = Bitboard::from(Rank::Three.relative(us));
     let r7bb = Bitboard::from(Rank::Seven.relative(us));
@@ -121,12 +326,20 @@ fn generate_pawn_moves(
     };
     let empty = !pos.all();
 
-    let fw = Color::pawn_push(us);
-    let back = Color::pawn_push(!us);
+    // Square offsets of a push and of the captures towards the h and a files
+    let (up, up_east, up_west): (i8, i8, i8) = match us {
+        Color::White => (8, 9, 7),
+        Color::Black => (-8, -7, -9),
+    };
+    let captures = |b: Bitboard| {
+        let ahead = forward(b, us);
+        ((ahead << 1).and_not(File::A), (ahead >> 1).and_not(File::H))
+    };
 
     if gt != GenType::Captures {
-        let mut b1 = fw(other) & empty;
-        let mut b2 = fw(b1 & r3bb) & empty;
+        let mut b1 = other.pawn_pushes(us, empty);
+        let mut b2 = (b1 & r3bb).pawn_pushes(us, empty) & restrict;
+        b1 &= restrict;
 
         if gt == GenType::Evasions {
             b1 &= target;
@@ -135,89 +348,46 @@ fn generate_pawn_moves(
 
         if gt == GenType::QuietChecks {
             let k = pos.king(!us);
-            let dc_candidates = pos.state().blockers(!us).and_not(k.file());
-            b1 &= pawn_attack(k, !us) | fw(dc_candidates);
-            b2 &= pawn_attack(k, !us) | fw(fw(dc_candidates));
+            let dc_candidates = forward(pos.blockers(!us).and_not(k.file()), us);
+            b1 &= pawn_attack(k, !us) | dc_candidates;
+            b2 &= pawn_attack(k, !us) | forward(dc_candidates, us);
         }
 
-        b1.map_by_board(|s| {
-            let to = s.get_square();
-            let from = back(s).get_square();
-            list.push(Move::new(from, to));
-        });
-        b2.map_by_board(|s| {
-            let to = s.get_square();
-            let from = back(back(s)).get_square();
-            list.push(Move::new(from, to));
-        });
+        push_pawn_moves(list, b1, up);
+        push_pawn_moves(list, b2, 2 * up);
     }
 
     if on_7.nonzero() {
-        let b1 = (fw(on_7) << 1).and_not(File::A) & enemies;
-        let b2 = (fw(on_7) >> 1).and_not(File::H) & enemies;
-        let mut b3 = fw(on_7) & empty;
+        let (east, west) = captures(on_7);
+        let mut pushes = forward(on_7, us) & empty & restrict;
 
         if gt == GenType::Evasions {
-            b3 &= target;
+            pushes &= target;
         }
 
-        let mut make_promos = |f: Square, t: Square| {
-            list.push(Move::new(f, t).add_promo(Knight));
-            list.push(Move::new(f, t).add_promo(Bishop));
-            list.push(Move::new(f, t).add_promo(Rook));
-            list.push(Move::new(f, t).add_promo(Queen));
-        };
-
-        b1.map_by_board(|s| {
-            let from = (back(s) >> 1).get_square();
-            let to = s.get_square();
-            make_promos(from, to);
-        });
-        b2.map_by_board(|s| {
-            let from = (back(s) << 1).get_square();
-            let to = s.get_square();
-            make_promos(from, to);
-        });
-        b3.map_by_board(|s| {
-            let from = back(s).get_square();
-            let to = s.get_square();
-            make_promos(from, to);
-        });
+        push_promotions(list, east & enemies & restrict, up_east);
+        push_promotions(list, west & enemies & restrict, up_west);
+        push_promotions(list, pushes, up);
     }
 
     if gt == GenType::Captures || gt == GenType::Evasions || gt == GenType::NonEvasions {
-        let b1 = (fw(other) << 1).and_not(File::A) & enemies;
-        let b2 = (fw(other) >> 1).and_not(File::H) & enemies;
-
-        b1.map_by_board(|s| {
-            let to = s.get_square();
-            let from = (back(s) >> 1).get_square();
-
-            list.push(Move::new(from, to));
-        });
-        b2.map_by_board(|s| {
-            let to = s.get_square();
-            let from = (back(s) << 1).get_square();
-
-            list.push(Move::new(from, to));
-        });
+        let (east, west) = captures(other);
+        push_pawn_moves(list, east & enemies & restrict, up_east);
+        push_pawn_moves(list, west & enemies & restrict, up_west);
 
-        if pos.state().ep().is_ok() {
+        if pos.state().ep().is_ok() && restrict.contains(pos.state().ep()) {
             let ep = pos.state().ep();
-            debug_assert_eq!(ep.rank(), Rank::Six.relative(us));
+            debug_assert_eq!(ep.relative_rank(us), Rank::Six);
 
-            if gt == GenType::Evasions && (target & fw(Bitboard::from(ep))).nonzero() {
+            if gt == GenType::Evasions && (target & forward(Bitboard::from(ep), us)).nonzero() {
                 return;
             }
 
-            let mut b1 = other & pawn_attack(ep, !us);
-
+            let b1 = other & pawn_attack(ep, !us);
             debug_assert!(b1.nonzero());
 
-            while b1.nonzero() {
-                let s = b1.get_square();
-                b1 &= Bitboard::new(b1.inner() - 1);
-                list.push(Move::new(s, ep).add_type(MType::EnPassant));
+            for s in b1 {
+                list.push(Move::en_passant(s, ep));
             }
         }
     }
@@ -236,8 +406,8 @@ fn generate_piece_moves(
             let s = pcs.pop_square();
             let mut b = gen_attacks(s, pt, pos.all(), pos.color(us)) & target;
 
-            if checks && (pt == Queen || (pos.state().blockers(!us) & s).zero()) {
-                b &= pos.state().check_squares(pt);
+            if checks && (pt == Queen || (pos.blockers(!us) & s).zero()) {
+                b &= pos.check_squares(pt);
             }
 
             while b.nonzero() {
@@ -248,7 +418,7 @@ fn generate_piece_moves(
     }
 }
 
-fn gen_attacks(square: Square, pt: PType, occ: Bitboard, friendly: Bitboard) -> Bitboard {
+pub(crate) fn gen_attacks(square: Square, pt: PType, occ: Bitboard, friendly: Bitboard) -> Bitboard {
     match pt {
         Pawn | King => panic!("Invalid piece type in `gen_attacks`"),
         Knight => knight_attack(square).and_not(friendly),
@@ -259,12 +429,25 @@ fn gen_attacks(square: Square, pt: PType, occ: Bitboard, friendly: Bitboard) ->
 }
 
 pub fn generate_for(pos: &Position, list: &mut MoveList, us: Color, gt: GenType) {
-    debug_assert_eq!(gt == GenType::Evasions, pos.state().checkers().nonzero());
+    generate_restricted(pos, list, us, gt, Bitboard::MAX);
+}
+
+// Like generate_for, but only moves landing on `restrict`
+fn generate_restricted(pos: &Position, list: &mut MoveList, us: Color, gt: GenType, restrict: Bitboard) {
+    debug_assert_eq!(
+        gt == GenType::Evasions,
+        pos.state().checkers().nonzero(),
+        "{gt:?} asked for while {}in check, Position::generate picks the right type",
+        if pos.in_check() { "" } else { "not " }
+    );
     let checks = gt == GenType::QuietChecks;
     let king = pos.king(us);
     let mut target = Bitboard::ZERO;
-    if gt != GenType::Evasions || !pos.state().checkers().more_than_one() {
+    // Against a double check, or one nothing can block or capture, only
+    // the king has moves
+    if gt != GenType::Evasions || pos.can_block_check() {
         target = if gt == GenType::Evasions {
+            // Just the checker itself unless it is a distant slider
             between::<true>(king, pos.state().checkers().get_square())
         } else if gt == GenType::NonEvasions {
             !pos.color(us)
@@ -274,19 +457,27 @@ pub fn generate_for(pos: &Position, list: &mut MoveList, us: Color, gt: GenType)
             !pos.all()
         };
 
-        generate_pawn_moves(pos, list, us, gt, target);
-        generate_piece_moves(pos, list, us, target, checks);
+        generate_pawn_moves(pos, list, us, gt, target, restrict);
+        generate_piece_moves(pos, list, us, target & restrict, checks);
     }
 
-    if !checks || (pos.state().blockers(!us) & king).nonzero() {
+    if !checks || (pos.blockers(!us) & king).nonzero() {
         let mask = if gt == GenType::Evasions {
-            !pos.color(us)
+            // The king can't step back along a checking slider's ray, the
+            // king itself is what blocks it there
+            let mut ray = Bitboard::ZERO;
+            let sliders = pos.state().checkers().and_not(pos.piece_2t(Pawn, Knight));
+            for s in sliders {
+                ray |= line(s, king) ^ s;
+            }
+            !(pos.color(us) | ray)
         } else {
             target
         };
-        let mut b = king_attack(king) & mask;
+        let mut b = king_attack(king) & mask & restrict;
         if checks {
-            b &= !queen_moves(pos.king(!us), Bitboard::ZERO);
+            let k = pos.king(!us);
+            b &= !(pseudo_rook_attack(k) | pseudo_bishop_attack(k));
         }
 
         while b.nonzero() {
@@ -296,25 +487,26 @@ pub fn generate_for(pos: &Position, list: &mut MoveList, us: Color, gt: GenType)
         }
 
         if gt == GenType::Quiet || gt == GenType::NonEvasions {
-            let (ksc, qsc) = pos.state().cur_castle().castle_for(us);
-            if ksc {
-                let up_to_rook = G1.relative(us);
-                let ib = between::<true>(king, up_to_rook);
-                if (pos.all() & ib).zero() {
-                    list.push(Move::new(king, G1.relative(us)).add_type(MType::Castle));
-                }
-            }
-            if qsc {
-                let up_to_rook = B1.relative(us);
-                let ib = between::<true>(king, up_to_rook);
-                if (pos.all() & ib).zero() {
-                    list.push(Move::new(king, C1.relative(us)).add_type(MType::Castle));
+            for king_side in [true, false] {
+                if let Some(m) = pos.castling_move(king_side) {
+                    if restrict.contains(m.to()) {
+                        list.push(m);
+                    }
                 }
             }
         }
     }
 }
 pub fn generate_legal<const CLEAR_PREV: bool>(pos: &Position, list: &mut MoveList) {
+    generate_legal_to::<CLEAR_PREV>(pos, Bitboard::MAX, list);
+}
+
+// Only the legal moves whose destination is in `targets`
+pub fn generate_legal_to<const CLEAR_PREV: bool>(
+    pos: &Position,
+    targets: Bitboard,
+    list: &mut MoveList,
+) {
     let us = pos.to_move();
     if CLEAR_PREV {
         list.clear();
@@ -325,10 +517,30 @@ pub fn generate_legal<const CLEAR_PREV: bool>(pos: &Position, list: &mut MoveLis
         GenType::Evasions
     };
 
-    let mut cur = list.len();
-    generate_for(pos, list, us, gt);
+    let start = list.len();
+    generate_restricted(pos, list, us, gt, targets);
+
+    #[cfg(debug_assertions)]
+    list.assert_no_duplicates();
+
+    filter_legal(pos, list, start);
+}
+
+// generate_for the side to move, keeping only the legal moves
+pub fn generate_for_legal(pos: &Position, gt: GenType, list: &mut MoveList) {
+    let start = list.len();
+    generate_for(pos, list, pos.to_move(), gt);
+    filter_legal(pos, list, start);
+}
+
+// Drops the illegal moves from list[start..]
+fn filter_legal(pos: &Position, list: &mut MoveList, start: usize) {
+    let us = pos.to_move();
+    let mut cur = start;
 
-    let pinned = pos.state().blockers(us) & pos.color(us);
+    // Evasions only block or capture a single checker (or move the king),
+    // so just like other moves they can only be illegal when pinned
+    let pinned = pos.blockers(us) & pos.color(us);
     let k = pos.king(us);
 
     while cur < list.len() {
@@ -343,3 +555,223 @@ pub fn generate_legal<const CLEAR_PREV: bool>(pos: &Position, list: &mut MoveLis
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::square::individual_squares::*;
+    use std::str::FromStr;
+
+    fn list_with_duplicate() -> MoveList {
+        let mut list = MoveList::new();
+        list.push(Move::new(E2, E4));
+        list.push(Move::new(D2, D4));
+        list.push(Move::new(E2, E4));
+        list
+    }
+
+    #[test]
+    fn legal_moves_restricted_to_targets() {
+        crate::init_for_tests();
+        let centre = Bitboard::from_squares(&[D4, E4, D5, E5]);
+        let enemy_camp = Bitboard::from(Rank::Six) | Bitboard::from(Rank::Seven);
+        for (fen, targets) in [
+            (Position::STARTPOS, centre),
+            (
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                enemy_camp,
+            ),
+        ] {
+            let pos = Position::from_str(fen).unwrap();
+            let mut all = MoveList::new();
+            let mut some = MoveList::new();
+            generate_legal::<true>(&pos, &mut all);
+            generate_legal_to::<true>(&pos, targets, &mut some);
+
+            let expected = (0..all.len())
+                .map(|i| all.get(i))
+                .filter(|m| targets.contains(m.to()))
+                .count();
+            assert!(!some.is_empty());
+            assert_eq!(some.len(), expected);
+            assert!((0..some.len()).all(|i| targets.contains(some.get(i).to())));
+        }
+    }
+
+    #[test]
+    fn legal_move_iterator() {
+        crate::init_for_tests();
+        let pos = Position::from_str(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        )
+        .unwrap();
+        let mut list = MoveList::new();
+        generate_legal::<true>(&pos, &mut list);
+
+        assert_eq!(pos.legal_move_iter().count(), list.len());
+        assert_eq!(pos.legal_move_iter().len(), 48);
+        assert_eq!((&pos).into_iter().collect::<Vec<_>>(), list.as_slice());
+
+        let mut castles = 0;
+        for m in &pos {
+            castles += (m.kind() == MType::Castle) as usize;
+        }
+        assert_eq!(castles, 2);
+    }
+
+    #[test]
+    fn legal_captures_only() {
+        crate::init_for_tests();
+        // The e4 knight is pinned, its captures must go
+        let pos = Position::from_str("4r1k1/8/3p1p2/8/4N3/8/8/4K3 w - - 0 1").unwrap();
+        let mut list = MoveList::new();
+        generate_for(&pos, &mut list, Color::White, GenType::Captures);
+        assert_eq!(list.len(), 2);
+
+        list.clear();
+        generate_for_legal(&pos, GenType::Captures, &mut list);
+        assert!(list.is_empty());
+
+        let pos = Position::from_str(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        )
+        .unwrap();
+        list.clear();
+        generate_for_legal(&pos, GenType::Captures, &mut list);
+        assert_eq!(list.len(), 8);
+        assert!((0..list.len()).all(|i| pos.is_legal_checked(list.get(i))));
+    }
+
+    #[test]
+    fn generate_follows_check_status() {
+        crate::init_for_tests();
+        // Only Bxf3 takes the checking knight, the king steps are quiet
+        let pos = Position::from_str("4k3/8/8/8/8/5n2/4B3/4K3 w - - 0 1").unwrap();
+        let captures = pos.generate(GenType::Captures);
+        assert_eq!(captures.as_slice(), &[Move::new(E2, F3)]);
+        let quiets = pos.generate(GenType::Quiet);
+        assert!(quiets.as_slice().iter().all(|&m| m.from() == E1));
+        assert_eq!(captures.len() + quiets.len(), pos.generate(GenType::Evasions).len());
+        assert_eq!(pos.generate(GenType::NonEvasions).len(), pos.legal_evasions().len());
+
+        let pos = Position::from_str(Position::STARTPOS).unwrap();
+        assert_eq!(pos.generate(GenType::Evasions).len(), 20);
+        assert!(pos.generate(GenType::Captures).is_empty());
+    }
+
+    #[test]
+    fn legal_evasions_resolve_check() {
+        crate::init_for_tests();
+        // Nxb5 takes the checker, Rc6 blocks, the king can step aside
+        let mut pos = Position::from_str("4k3/8/r7/1B6/8/2n5/8/4K2R b - - 0 1").unwrap();
+        let evasions = pos.legal_evasions();
+        assert!(evasions.as_slice().contains(&Move::new(C3, B5)));
+        assert!(evasions.as_slice().contains(&Move::new(A6, C6)));
+        assert!(evasions.as_slice().contains(&Move::new(E8, F7)));
+        assert_eq!(evasions.len(), pos.legal_moves().len());
+        for m in evasions {
+            pos.do_move(m);
+            assert!(!pos.is_in_check(Color::Black), "{m}");
+            pos.undo_move(m);
+        }
+
+        let mated = Position::from_str("R5k1/5ppp/8/8/8/8/8/4K3 b - - 0 1").unwrap();
+        assert!(mated.legal_evasions().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "in check")]
+    fn legal_evasions_outside_check_panics() {
+        crate::init_for_tests();
+        Position::startpos().legal_evasions();
+    }
+
+    #[test]
+    fn evasions_match_full_filter() {
+        crate::init_for_tests();
+        let mut rng = crate::prng::Prng::new(0xe7a5);
+        let mut legal = MoveList::new();
+        let mut evasions = MoveList::new();
+        let mut checks_seen = 0;
+
+        for fen in [
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        ] {
+            for _ in 0..40 {
+                let mut pos = Position::from_str(fen).unwrap();
+                for _ in 0..60 {
+                    generate_legal::<true>(&pos, &mut legal);
+                    if legal.is_empty() {
+                        break;
+                    }
+                    if pos.in_check() {
+                        checks_seen += 1;
+                        evasions.clear();
+                        generate_for(&pos, &mut evasions, pos.to_move(), GenType::Evasions);
+                        let expected = (0..evasions.len())
+                            .filter(|&i| pos.is_legal(evasions.get(i)))
+                            .count();
+                        assert_eq!(legal.len(), expected, "{}", pos.fen());
+                    }
+                    pos.do_move(legal.get(rng.sample() as usize % legal.len()));
+                }
+            }
+        }
+        assert!(checks_seen > 50);
+    }
+
+    #[test]
+    fn pick_best_matches_full_sort() {
+        let mut list = MoveList::new();
+        let scores = [5, -3, 40, 0, 40, 12, -7, 8];
+        for (i, &score) in scores.iter().enumerate() {
+            list.push(Move::new(A1, unsafe { Square::new(i as u8 + 8) }));
+            list.set_score(i, score);
+        }
+
+        let mut sorted = scores.to_vec();
+        sorted.sort_by(|a, b| b.cmp(a));
+        let picked = (0..list.len())
+            .map(|i| {
+                let m = list.pick_best(i);
+                assert_eq!(list.get(i), m);
+                list.score(i)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(picked, sorted);
+    }
+
+    #[test]
+    fn dedup_removes_repeats() {
+        let mut list = list_with_duplicate();
+        list.dedup();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list.get(0), Move::new(E2, E4));
+        assert_eq!(list.get(1), Move::new(D2, D4));
+    }
+
+    #[test]
+    fn scores_follow_their_moves() {
+        let mut list = list_with_duplicate();
+        list.push(Move::new(G1, F3));
+        for (i, score) in [10, 20, 30, 40].into_iter().enumerate() {
+            list.set_score(i, score);
+        }
+        list.dedup();
+        assert_eq!(list.as_slice(), [Move::new(E2, E4), Move::new(D2, D4), Move::new(G1, F3)]);
+        assert_eq!((0..list.len()).map(|i| list.score(i)).collect::<Vec<_>>(), [10, 20, 40]);
+
+        list.replace(vec![Move::new(B1, C3), Move::new(G1, F3)]);
+        assert_eq!(list.len(), 2);
+        assert_eq!((list.score(0), list.score(1)), (0, 0));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Duplicate move generated")]
+    fn duplicate_assertion_fires() {
+        list_with_duplicate().assert_no_duplicates();
+    }
+}
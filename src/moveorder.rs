@@ -24,51 +24,300 @@ use crate::prelude::pawn_attack;
 use crate::prelude::{MType::*, Move, MoveList};
 use crate::prelude::PType::*;
 use crate::prelude::Piece;
+use crate::tt;
 
 const CAP_SCORE: i32 = 10;
 const CONTROL_BY_OPP_PAWN_SCORE: i32 = 350;
-/* const TT_MOVE_SCORE: i32 = 10_000; */
+const CHECK_SCORE: i32 = 400;
 
-pub fn order_moves(pos: &Position, move_list: &mut MoveList/*, tt: TransposeTable*/) {
-    /* let hashm = tt.get_stored(); */
-    let mut scores = Vec::with_capacity(move_list.len());
-    for i in 0..move_list.len() {
-        let mut score = 0;
-        let m = move_list.get(i);
+const MAX_HISTORY: i32 = 1 << 14;
+const COUNTERMOVE_SCORE: i32 = 1000;
+
+// Quiet move statistics gathered over one search
+#[derive(Debug, Clone)]
+pub struct History {
+    // Butterfly history, indexed [from][to]
+    butterfly: [[i32; 64]; 64],
+    // The quiet reply that last refuted a move, indexed [from][to] of that move
+    countermoves: [[Move; 64]; 64],
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            butterfly: [[0; 64]; 64],
+            countermoves: [[Move::NULL; 64]; 64],
+        }
+    }
+
+    #[inline]
+    pub fn history(&self, m: Move) -> i32 {
+        self.butterfly[m.from().inner() as usize][m.to().inner() as usize]
+    }
+
+    // Positive bonus for quiets causing a cutoff, negative for those that didn't
+    pub fn update_history(&mut self, m: Move, bonus: i32) {
+        let bonus = bonus.clamp(-MAX_HISTORY, MAX_HISTORY);
+        let h = &mut self.butterfly[m.from().inner() as usize][m.to().inner() as usize];
+        // Gravity keeps the entries bounded by MAX_HISTORY
+        *h += bonus - *h * bonus.abs() / MAX_HISTORY;
+    }
+
+    #[inline]
+    pub fn countermove(&self, prev: Move) -> Move {
+        if !prev.is_ok() {
+            return Move::NULL;
+        }
+        self.countermoves[prev.from().inner() as usize][prev.to().inner() as usize]
+    }
+
+    pub fn update_countermove(&mut self, prev: Move, m: Move) {
+        if prev.is_ok() {
+            self.countermoves[prev.from().inner() as usize][prev.to().inner() as usize] = m;
+        }
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[inline]
+pub fn is_quiet(pos: &Position, m: Move) -> bool {
+    !pos.is_capture(m) && m.kind() != Promotion
+}
+
+fn score_move(pos: &Position, m: Move) -> i32 {
+    let mut score = 0;
+
+    debug_assert!(m.is_ok());
+
+    let from = m.from();
+    let to = m.to();
+    let k = m.kind();
+    let prom = m.promo();
+
+    let us = pos.to_move();
+    let them = !us;
+
+    let p = pos.piece_on(from);
+
+    if pos.is_capture(m) {
+        score = CAP_SCORE * pos.capture_value(m) - p.kind().value();
+    }
+
+    if p.kind() == Pawn {
+        if k == Promotion {
+            score += prom.value();
+        }
+    } else if (pawn_attack(to, us) & pos.spec(Pawn, them)).nonzero() {
+        score -= CONTROL_BY_OPP_PAWN_SCORE;
+    }
+
+    if pos.gives_check(m) {
+        score += CHECK_SCORE;
+    }
+
+    score
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+enum Stage {
+    TTMove,
+    GoodCaptures,
+    Quiets,
+    BadCaptures,
+    Done,
+}
+
+// Yields the hash move, then winning and equal captures, then quiet moves, and
+// captures that lose material (negative SEE) last. Losing captures
+// are only deferred, since they may still be the best or only move.
+#[derive(Debug, Clone)]
+pub struct MovePicker {
+    stage: Stage,
+    tt_move: Option<Move>,
+    // Each stage is sorted so the best move is popped off the end
+    good_captures: Vec<(i32, Move)>,
+    quiets: Vec<(i32, Move)>,
+    bad_captures: Vec<(i32, Move)>,
+}
 
-        debug_assert!(m.is_ok());
+impl MovePicker {
+    // `counter` is the countermove to the move that led here, it is tried
+    // before the other quiets
+    pub fn with_counter(pos: &Position, move_list: &MoveList, counter: Move) -> Self {
+        let mut good_captures = Vec::new();
+        let mut quiets = Vec::new();
+        let mut bad_captures = Vec::new();
 
-        let from = m.from();
-        let to = m.to();
-        let k = m.kind();
-        let prom = m.promo();
+        // Only trust the hash move if it is one of our (legal) moves. A key
+        // collision can hand us a move for another position entirely, and
+        // do_move assumes legal input.
+        let tt_move = tt::probe(pos.state().key())
+            .map(|e| e.best_move)
+            .filter(|&m| m.is_ok())
+            .filter(|&m| (0..move_list.len()).any(|i| move_list.get(i) == m));
 
-        let us = pos.to_move();
-        let them = !us;
+        for i in 0..move_list.len() {
+            let m = move_list.get(i);
+            if Some(m) == tt_move {
+                continue;
+            }
+            let score = score_move(pos, m);
+            if is_quiet(pos, m) {
+                let bonus = if m == counter { COUNTERMOVE_SCORE } else { 0 };
+                quiets.push((score + bonus, m));
+            } else if pos.see(m) < 0 {
+                bad_captures.push((score, m));
+            } else {
+                good_captures.push((score, m));
+            }
+        }
 
-        let p = pos.piece_on(from);
-        let cap = pos.piece_on(to);
+        for stage in [&mut good_captures, &mut quiets, &mut bad_captures] {
+            stage.sort_by_key(|&(score, _)| score);
+        }
 
-        if cap.is_ok() {
-            score = CAP_SCORE * cap.kind().value() - p.kind().value();
+        Self {
+            stage: Stage::TTMove,
+            tt_move,
+            good_captures,
+            quiets,
+            bad_captures,
         }
+    }
+}
 
-        if p.kind() == Pawn {
-            if k == Promotion {
-                score += prom.value();
+impl Iterator for MovePicker {
+    type Item = Move;
+    fn next(&mut self) -> Option<Move> {
+        if self.stage == Stage::TTMove {
+            self.stage = Stage::GoodCaptures;
+            if let Some(m) = self.tt_move {
+                return Some(m);
             }
-        } else if (pawn_attack(to, us) & pos.spec(Pawn, them)).nonzero() {
-            score -= CONTROL_BY_OPP_PAWN_SCORE;
         }
 
-        // if m == hash {
-        //     score += TT_MOVE_SCORE;
-        // }
+        loop {
+            let bucket = match self.stage {
+                Stage::GoodCaptures => &mut self.good_captures,
+                Stage::Quiets => &mut self.quiets,
+                Stage::BadCaptures => &mut self.bad_captures,
+                Stage::TTMove | Stage::Done => return None,
+            };
+            if let Some((_, m)) = bucket.pop() {
+                return Some(m);
+            }
+            self.stage = match self.stage {
+                Stage::GoodCaptures => Stage::Quiets,
+                Stage::Quiets => Stage::BadCaptures,
+                _ => Stage::Done,
+            };
+        }
+    }
+}
 
-        scores.push((score, m));
+// Fills in the list's scores for use with MoveList::pick_best
+pub fn score_moves(pos: &Position, move_list: &mut MoveList) {
+    for i in 0..move_list.len() {
+        move_list.set_score(i, score_move(pos, move_list.get(i)));
     }
+}
+
+pub fn order_moves(pos: &Position, move_list: &mut MoveList) {
+    order_moves_with_counter(pos, move_list, Move::NULL);
+}
+
+// `prev` is the move that led to `pos`, its countermove is tried first among the quiets
+pub fn order_moves_after(pos: &Position, move_list: &mut MoveList, history: &History, prev: Move) {
+    order_moves_with_counter(pos, move_list, history.countermove(prev));
+}
 
-    scores.sort_by(|(s1, _), (s2, _)| s1.partial_cmp(s2).unwrap());
-    let mut isolated_moves = scores.into_iter().map(|(_, m)| m).collect::<Vec<_>>();
-    move_list.replace(isolated_moves);
+fn order_moves_with_counter(pos: &Position, move_list: &mut MoveList, counter: Move) {
+    let ordered = MovePicker::with_counter(pos, move_list, counter).collect::<Vec<_>>();
+    move_list.replace(ordered);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::{generate_legal, individual_squares::*};
+    use std::str::FromStr;
+
+    #[test]
+    fn losing_capture_comes_after_quiets() {
+        crate::init_for_tests();
+        let pos = Position::from_str("4k3/8/4p3/3p4/8/8/8/3QK3 w - - 0 1").unwrap();
+        let mut moves = MoveList::new();
+        generate_legal::<true>(&pos, &mut moves);
+
+        let bad = Move::new(D1, D5);
+        assert!(pos.see(bad) < 0);
+
+        let picked = MovePicker::with_counter(&pos, &moves, Move::NULL).collect::<Vec<_>>();
+        assert_eq!(picked.len(), moves.len());
+        assert_eq!(picked.last(), Some(&bad));
+        assert!(picked[..picked.len() - 1].iter().all(|&m| is_quiet(&pos, m)));
+    }
+
+    #[test]
+    fn illegal_tt_move_is_skipped() {
+        crate::init_for_tests();
+        let fen = "4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1";
+        let mut pos = Position::from_str(fen).unwrap();
+        let mut moves = MoveList::new();
+        generate_legal::<true>(&pos, &mut moves);
+
+        // As if a colliding position had stored these, or nothing beat alpha
+        for bogus in [
+            Move::new(D5, D1),
+            Move::new(A2, A4),
+            Move::castle(E1, H1),
+            Move::null_move(),
+        ] {
+            tt::store(pos.state().key(), bogus, 0, 1, tt::Bound::Exact);
+            assert_eq!(pos.tt_move(), None);
+
+            let picked = MovePicker::with_counter(&pos, &moves, Move::NULL).collect::<Vec<_>>();
+            assert_eq!(picked.len(), moves.len());
+            assert!(!picked.contains(&bogus));
+
+            let mut best = Move::NULL;
+            crate::evaluate::alpha_beta(&mut pos, &mut best, 3);
+            assert_eq!(best, Move::new(D1, D5));
+            assert_eq!(pos.fen(), Position::from_str(fen).unwrap().fen());
+        }
+    }
+
+    #[test]
+    fn countermove_leads_the_quiets() {
+        crate::init_for_tests();
+        let mut pos = Position::startpos();
+        let prev = Move::new(E2, E4);
+        pos.do_move(prev);
+        let mut moves = MoveList::new();
+        generate_legal::<true>(&pos, &mut moves);
+
+        let reply = Move::new(A7, A6);
+        let first_quiet = |counter| {
+            MovePicker::with_counter(&pos, &moves, counter).find(|&m| is_quiet(&pos, m))
+        };
+        assert_ne!(first_quiet(Move::NULL), Some(reply));
+
+        let mut history = History::new();
+        history.update_countermove(prev, reply);
+        let counter = history.countermove(prev);
+        assert_eq!(counter, reply);
+        assert_eq!(first_quiet(counter), Some(reply));
+
+        let mut ordered = moves.clone();
+        order_moves_after(&pos, &mut ordered, &history, prev);
+        assert_eq!(ordered.as_slice().iter().find(|&&m| is_quiet(&pos, m)), Some(&reply));
+
+        history.update_countermove(Move::NULL, reply);
+        assert_eq!(history.countermove(Move::NULL), Move::NULL);
+    }
 }
@@ -70,6 +70,33 @@ impl Piece {
             _ => unreachable!(),
         }
     }
+
+    // Non-panicking variants for squares that may be empty
+    #[inline]
+    pub const fn color_opt(self) -> Option<Color> {
+        if self.is_ok() {
+            Some(self.color())
+        } else {
+            None
+        }
+    }
+    #[inline]
+    pub const fn kind_opt(self) -> Option<PType> {
+        if self.is_ok() {
+            Some(self.kind())
+        } else {
+            None
+        }
+    }
+    // Material value of the piece, 0 for an empty square
+    #[inline]
+    pub const fn value(self) -> i32 {
+        if self.is_ok() {
+            self.kind().value()
+        } else {
+            0
+        }
+    }
 }
 
 impl PType {
@@ -151,3 +178,26 @@ impl fmt::Display for Piece {
         write!(f, "{ch}")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn null_piece_options() {
+        assert_eq!(Piece::NULL.kind_opt(), None);
+        assert_eq!(Piece::NULL.color_opt(), None);
+        assert_eq!(Piece(6).kind_opt(), None);
+
+        let p = Piece::new(PType::Rook, Color::Black);
+        assert_eq!(p.kind_opt(), Some(PType::Rook));
+        assert_eq!(p.color_opt(), Some(Color::Black));
+    }
+
+    #[test]
+    fn piece_values() {
+        assert_eq!(Piece::new(PType::Queen, Color::White).value(), 900);
+        assert_eq!(Piece::new(PType::Knight, Color::Black).value(), 300);
+        assert_eq!(Piece::NULL.value(), 0);
+    }
+}
@@ -17,14 +17,15 @@
 */
 
 use std::fmt;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use std::str::FromStr;
 
 use crate::prelude::individual_squares::*;
-use crate::{prelude::*, zobrist::Key};
+use crate::movegen::gen_attacks;
+use crate::{prelude::*, tt, zobrist::Key};
 use Color::*;
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct Position {
     board: [Piece; 64],
     pieces: [Bitboard; 6],
@@ -33,18 +34,26 @@ pub struct Position {
     ply: i32,
     to_move: Color,
     state: State,
+
+    // Files of the rooks each side castles with, king side first. Always
+    // h and a in standard chess, anywhere on the back rank in Chess960.
+    castling_rooks: [[File; 2]; 2],
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct State {
-    check_squares: [Bitboard; 6],
     castle: Castle,
     ep: Square, // Just have Square(64) for not-available
     rule50: i32,
 
     checkers: Bitboard,
-    blockers: [Bitboard; 2],
-    pinners: [Bitboard; 2],
+    // Pieces shielding the side to move's king, and the enemy sliders
+    // pinning them. Needed by every legality check, so always computed.
+    blockers: Bitboard,
+    pinners: Bitboard,
+    // Only gives_check and check generation need these, so they are
+    // filled in on first use (OnceLock so State stays Sync)
+    check_info: OnceLock<CheckInfo>,
 
     captured: Piece,
 
@@ -54,9 +63,57 @@ pub struct State {
     prev: Option<Arc<State>>,
 }
 
+// What do_move found out while making a move
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct MoveInfo {
+    // Piece::NULL for non-captures, their pawn for en passant
+    pub captured: Piece,
+    pub gives_check: bool,
+}
+
+// What the side to move needs to know to give check
+#[derive(Debug, Clone, Copy)]
+struct CheckInfo {
+    check_squares: [Bitboard; 6],
+    // Blockers of their king, any of ours among them can discover check
+    blockers: Bitboard,
+    // Our sliders pinning their pieces
+    pinners: Bitboard,
+}
+
 #[derive(Default, Debug, PartialEq, Eq, Clone, Copy)]
 pub struct Castle(u8);
 
+#[derive(Default, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum GameResult {
+    #[default]
+    Ongoing,
+    Checkmate,
+    Stalemate,
+}
+
+// The first broken invariant Position::validate ran into
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PositionError {
+    KingCount(Color),
+    OpponentInCheck,
+    PawnOnBackRank(Square),
+    BadEnPassant(Square),
+    BadCastling(Color),
+}
+
+impl fmt::Display for PositionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::KingCount(c) => write!(f, "{c:?} does not have exactly one king"),
+            Self::OpponentInCheck => write!(f, "The side not to move is in check"),
+            Self::PawnOnBackRank(s) => write!(f, "Pawn on the back rank at {s}"),
+            Self::BadEnPassant(s) => write!(f, "No pawn can be captured en passant on {s}"),
+            Self::BadCastling(c) => write!(f, "{c:?} castling rights without king and rook"),
+        }
+    }
+}
+
 impl Position {
     pub const STARTPOS: &'static str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
 
@@ -86,6 +143,12 @@ impl Position {
         self.color(White).const_or(self.color(Black))
     }
 
+    // The raw piece type and color boards, indexed by PType and Color
+    #[inline(always)]
+    pub const fn bitboards(&self) -> (&[Bitboard; 6], &[Bitboard; 2]) {
+        (&self.pieces, &self.colors)
+    }
+
     #[inline(always)]
     pub const fn king(&self, color: Color) -> Square {
         let s = self.spec(PType::King, color).get_square();
@@ -93,6 +156,46 @@ impl Position {
         s
     }
 
+    // The square of the rook `color` castles with on that side
+    #[inline]
+    pub fn castling_rook(&self, color: Color, king_side: bool) -> Square {
+        let file = self.castling_rooks[color as usize][!king_side as usize];
+        Square::create(file, Rank::One.relative(color))
+    }
+
+    // The castling move to that side if the side to move still has the
+    // right and nothing stands in the way. Attacked squares are is_legal's job.
+    pub fn castling_move(&self, king_side: bool) -> Option<Move> {
+        let us = self.to_move;
+        if self.state.castle.inner() & Castle::bit(us, king_side) == 0 {
+            return None;
+        }
+        let king = self.king(us);
+        let rook = self.castling_rook(us, king_side);
+        if self.piece_on(rook) != Piece::new(PType::Rook, us) {
+            return None;
+        }
+
+        // Both pieces' paths, apart from the two of them, must be empty
+        let m = Move::castle(king, rook);
+        let (king_to, rook_to) = m.castle_destinations();
+        let path = between::<true>(king, king_to) | between::<true>(rook, rook_to);
+        (path & (self.all() ^ king ^ rook)).zero().then_some(m)
+    }
+
+    // The rights lost once a piece moves from or is captured on `s`
+    fn castle_rights_on(&self, s: Square) -> u8 {
+        let mut bits = 0;
+        for c in [White, Black] {
+            for king_side in [true, false] {
+                if self.castling_rook(c, king_side) == s {
+                    bits |= Castle::bit(c, king_side);
+                }
+            }
+        }
+        bits
+    }
+
     #[inline(always)]
     pub const fn ply(&self) -> i32 {
         self.ply
@@ -111,43 +214,119 @@ impl Position {
         self.state.checkers.nonzero()
     }
 
-    // FIXME sometimes throws an error when line(s, s) isn't given an okay square
+    // The Zobrist key, blind to the halfmove clock and ply so that
+    // transpositions land on the same TT entry
+    #[inline(always)]
+    pub const fn transposition_key(&self) -> Key {
+        self.state.key
+    }
+
+    // Also tells positions apart by their halfmove clock, for caches where
+    // being near a fifty-move draw changes the answer
+    #[inline]
+    pub fn full_key(&self) -> Key {
+        self.state.key ^ zobrist::rule50(self.state.rule50)
+    }
+
+    // Whether `color`'s king is attacked, whoever is to move
+    #[inline]
+    pub fn is_in_check(&self, color: Color) -> bool {
+        if color == self.to_move {
+            self.in_check()
+        } else {
+            self.is_attacked_by(self.king(color), self.all(), !color)
+        }
+    }
+
+    #[inline(always)]
+    pub const fn checking_pieces(&self) -> Bitboard {
+        self.state.checkers
+    }
+
+    // Whether anything but the king can answer the check, by capturing the
+    // checker or stepping in front of it. Pinned pieces never can, and
+    // neither can anything against a double check.
+    pub fn can_block_check(&self) -> bool {
+        let checkers = self.checking_pieces();
+        if checkers.zero() || checkers.more_than_one() {
+            return false;
+        }
+        let us = self.to_move();
+        let king = self.king(us);
+        let checker = checkers.get_square();
+        let defenders = self.color(us).and_not(self.spec(PType::King, us) | self.blockers(us));
+        let pawns = self.spec(PType::Pawn, us) & defenders;
+
+        let pieces = defenders.and_not(pawns);
+        if between::<true>(king, checker)
+            .into_iter()
+            .any(|s| (self.attackers_by(s, self.all(), us) & pieces).nonzero())
+        {
+            return true;
+        }
+
+        // Pawns only capture diagonally, en passant included, and block by pushing
+        if (pawn_attack(checker, !us) & pawns).nonzero() {
+            return true;
+        }
+        let ep = self.state.ep;
+        if ep.is_ok() && ep.file() == checker.file() && (pawn_attack(ep, !us) & pawns).nonzero() {
+            return true;
+        }
+        let empty = !self.all();
+        let single = pawns.pawn_pushes(us, empty);
+        let double = (single & Bitboard::from(Rank::Three.relative(us))).pawn_pushes(us, empty);
+        ((single | double) & between::<false>(king, checker)).nonzero()
+    }
+
     pub fn gives_check(&self, m: Move) -> bool {
-        let p = self.piece_on(m.from());
-        let dest = m.to();
-        let cap = self.piece_on(dest);
+        let us = self.to_move();
+        let from = m.from();
+        let to = m.to();
+        let p = self.piece_on(from);
+        let cap = self.piece_on(to);
         debug_assert!(p.is_ok());
-        debug_assert_eq!(p.color(), self.to_move());
-        debug_assert!(!cap.is_ok() || cap.color() != self.to_move());
+        debug_assert_eq!(p.color(), us);
+        debug_assert!(m.kind() == MType::Castle || !cap.is_ok() || cap.color() != us);
 
-        let is_moving_to_check = (self.state().check_squares(p.kind()) & dest).nonzero();
-        let blocker = self.state().blockers(!self.to_move()) & m.from();
-        let is_blocker = blocker.nonzero();
+        let k = self.king(!us);
 
-        if !is_blocker {
-            return is_moving_to_check;
+        if (self.check_squares(p.kind()) & to).nonzero() {
+            return true;
         }
 
-        let is_discovery = if !is_blocker {
-            false
-        } else {
-            let k = self.king(!self.to_move());
-            let mut discoverer = Square::NULL;
-            self.state()
-                .pinners(self.to_move())
-                .map_by_square(|pinner| {
-                    if discoverer.is_ok() {
-                        return;
-                    }
-                    if pinner.in_line2(blocker.get_square(), k) {
-                        discoverer = pinner;
-                    }
-                });
-
-            discoverer.is_ok() && discoverer.in_line2(blocker.get_square(), k)
-        };
+        // Discovered check by moving a blocker off the line to their king
+        if (self.blockers(!us) & from).nonzero() && !Square::aligned(from, to, k) {
+            return true;
+        }
 
-        is_discovery || is_moving_to_check
+        match m.kind() {
+            MType::Normal => false,
+            MType::Promotion => {
+                let occ = self.all() ^ from;
+                let attacks = match m.promo() {
+                    PType::Knight => knight_attack(to),
+                    PType::Bishop => bishop_moves(to, occ),
+                    PType::Rook => rook_moves(to, occ),
+                    _ => queen_moves(to, occ),
+                };
+                (attacks & k).nonzero()
+            }
+            MType::EnPassant => {
+                let cap_sq = Square::create(to.file(), from.rank());
+                let occ = (self.all() ^ from ^ cap_sq) | to;
+                (rook_moves(k, occ) & self.spec_2t(PType::Queen, PType::Rook, us)).nonzero()
+                    || (bishop_moves(k, occ) & self.spec_2t(PType::Queen, PType::Bishop, us))
+                        .nonzero()
+            }
+            MType::Castle => {
+                // The check squares still have our king on its old square,
+                // which can hide a check along the back rank
+                let (king_to, rook_to) = m.castle_destinations();
+                let occ = (self.all() ^ from ^ to) | king_to | rook_to;
+                rook_moves(rook_to, occ).contains(k)
+            }
+        }
     }
 
     #[inline]
@@ -160,18 +339,82 @@ impl Position {
             | (pawn_attack(square, Black) & self.spec(PType::Pawn, White));
         let knights = knight_attack(square) & self.piece(PType::Knight);
         let kings = king_attack(square) & self.piece(PType::King);
-        let bish = bishop_moves(square, occ) & self.piece_2t(PType::Bishop, PType::Queen);
-        let rook = rook_moves(square, occ) & self.piece_2t(PType::Rook, PType::Queen);
 
-        pawns | knights | kings | bish | rook
+        pawns | knights | kings | self.slider_attackers(square, occ, self.all())
+    }
+
+    // attacks_to, but with each attacker's square and piece spelled out
+    pub fn attacker_list(&self, square: Square) -> Vec<(Square, Piece)> {
+        self.attacks_to(square)
+            .into_iter()
+            .map(|s| (s, self.piece_on(s)))
+            .collect()
+    }
+
+    // Bishops, rooks and queens among `pieces` hitting `square`. The magic
+    // lookups are skipped when no such slider is even on an open line.
+    #[inline(always)]
+    fn slider_attackers(&self, square: Square, occ: Bitboard, pieces: Bitboard) -> Bitboard {
+        let queens = self.piece(PType::Queen);
+        let diag = (self.piece(PType::Bishop) | queens) & pieces;
+        let orth = (self.piece(PType::Rook) | queens) & pieces;
+        let mut attackers = Bitboard::ZERO;
+
+        if (pseudo_bishop_attack(square) & diag).nonzero() {
+            attackers |= bishop_moves(square, occ) & diag;
+        }
+        if (pseudo_rook_attack(square) & orth).nonzero() {
+            attackers |= rook_moves(square, occ) & orth;
+        }
+        attackers
+    }
+
+    // Every square the piece on `from` hits, own pieces included and pins
+    // ignored. Empty for an empty square.
+    pub fn piece_attacks(&self, from: Square) -> Bitboard {
+        let p = self.piece_on(from);
+        if !p.is_ok() {
+            return Bitboard::ZERO;
+        }
+        match p.kind() {
+            PType::Pawn => pawn_attack(from, p.color()),
+            PType::King => king_attack(from),
+            pt => gen_attacks(from, pt, self.all(), Bitboard::ZERO),
+        }
+    }
+
+    // Only the attackers of one color, cheaper than masking attacks_to_occ
+    pub fn attackers_by(&self, square: Square, occ: Bitboard, color: Color) -> Bitboard {
+        debug_assert!(square.is_ok());
+        let them = self.color(color);
+        let pawns = pawn_attack(square, !color) & self.piece(PType::Pawn);
+        let knights = knight_attack(square) & self.piece(PType::Knight);
+        let kings = king_attack(square) & self.piece(PType::King);
+
+        ((pawns | knights | kings) & them) | self.slider_attackers(square, occ, them)
+    }
+
+    // Whether `color` attacks `square` at all, trying the cheap pieces first
+    pub fn is_attacked_by(&self, square: Square, occ: Bitboard, color: Color) -> bool {
+        debug_assert!(square.is_ok());
+        let them = self.color(color);
+        (pawn_attack(square, !color) & self.piece(PType::Pawn) & them).nonzero()
+            || (knight_attack(square) & self.piece(PType::Knight) & them).nonzero()
+            || (king_attack(square) & self.piece(PType::King) & them).nonzero()
+            || self.slider_attackers(square, occ, them).nonzero()
+    }
+
+    // Attackers of the side to move's king, cached as the checkers
+    #[inline(always)]
+    pub const fn king_attackers(&self) -> Bitboard {
+        self.state.checkers
     }
 
     fn slider_blockers(&self, sliders: Bitboard, square: Square) -> (Bitboard, Bitboard) {
         let mut blockers = Bitboard::ZERO;
         let mut pinners = Bitboard::ZERO;
-        let mut snipers = ((rook_moves(square, Bitboard::ZERO)
-            & self.piece_2t(PType::Rook, PType::Queen))
-            | (bishop_moves(square, Bitboard::ZERO) & self.piece_2t(PType::Bishop, PType::Queen)))
+        let mut snipers = ((pseudo_rook_attack(square) & self.piece_2t(PType::Rook, PType::Queen))
+            | (pseudo_bishop_attack(square) & self.piece_2t(PType::Bishop, PType::Queen)))
             & sliders;
 
         let occ = self.all() ^ snipers;
@@ -212,8 +455,78 @@ impl Position {
         p
     }
 
+    // Whether `mv` is a move movegen could produce here, ignoring only pins
+    // and the king stepping into check (is_legal covers those). Safe on any
+    // input, e.g. a hash move from a colliding position or a parsed string.
     pub fn is_pseudo_legal(&self, mv: Move) -> bool {
-        todo!();
+        let us = self.to_move();
+        let from = mv.from();
+        let to = mv.to();
+        let moved = self.piece_on(from);
+        let cap = self.piece_on(to);
+
+        if !mv.is_ok() || !moved.is_ok() || moved.color() != us {
+            return false;
+        }
+        let checkers = self.state.checkers;
+        if mv.kind() == MType::Castle {
+            let generated = |king_side| self.castling_move(king_side) == Some(mv);
+            return checkers.zero() && (generated(true) || generated(false));
+        }
+        if cap.is_ok() && (cap.color() == us || cap.kind() == PType::King) {
+            return false;
+        }
+
+        let kind_ok = match mv.kind() {
+            MType::Normal => mv == Move::new(from, to),
+            MType::EnPassant => mv == Move::en_passant(from, to),
+            MType::Castle => unreachable!(),
+            MType::Promotion => [PType::Knight, PType::Bishop, PType::Rook, PType::Queen]
+                .into_iter()
+                .any(|pt| mv == Move::promotion(from, to, pt)),
+        };
+        if !kind_ok {
+            return false;
+        }
+
+        let last_rank = to.relative_rank(us) == Rank::Eight;
+        if moved.kind() == PType::Pawn {
+            if (mv.kind() == MType::Promotion) != last_rank {
+                return false;
+            }
+            let empty = !self.all();
+            let reachable = if mv.kind() == MType::EnPassant {
+                to == self.state.ep && pawn_attack(from, us).contains(to)
+            } else if cap.is_ok() {
+                pawn_attack(from, us).contains(to)
+            } else {
+                let push = Bitboard::from(from).pawn_pushes(us, empty);
+                let double = if from.relative_rank(us) == Rank::Two {
+                    push.pawn_pushes(us, empty)
+                } else {
+                    Bitboard::ZERO
+                };
+                (push | double).contains(to)
+            };
+            if !reachable {
+                return false;
+            }
+        } else if mv.kind() != MType::Normal || !self.piece_attacks(from).contains(to) {
+            return false;
+        }
+
+        // In check, anything but the king has to take the checker or block
+        if checkers.nonzero() && moved.kind() != PType::King {
+            if checkers.more_than_one() {
+                return false;
+            }
+            let checker = checkers.get_square();
+            let blocks = between::<true>(self.king(us), checker).contains(to);
+            let takes_ep = mv.kind() == MType::EnPassant
+                && checker == Square::create(to.file(), from.rank());
+            return blocks || takes_ep;
+        }
+        true
     }
     pub fn is_legal(&self, mv: Move) -> bool {
         let us = self.to_move();
@@ -227,12 +540,16 @@ impl Position {
         debug_assert_eq!(moved.color(), us);
         debug_assert!(moved.is_ok());
 
+        // Only the king can escape a double check
+        if self.state().checkers().more_than_one() && from != k {
+            return false;
+        }
+
         if ty == MType::EnPassant {
-            let bw = (!us).pawn_push();
             debug_assert_eq!(self.state().ep(), to);
             debug_assert_eq!(moved, Piece::new(PType::Pawn, us));
             debug_assert_eq!(cap, Piece::NULL);
-            let pawn_cap_sq = bw(Bitboard::from(to)).get_square();
+            let pawn_cap_sq = Square::create(to.file(), from.rank());
             let pawn_cap = self.piece_on(pawn_cap_sq);
             debug_assert_eq!(pawn_cap, Piece::new(PType::Pawn, !us));
 
@@ -240,28 +557,29 @@ impl Position {
             return (rook_moves(k, occ) & self.spec_2t(PType::Queen, PType::Rook, !us)).zero()
                 && (bishop_moves(k, occ) & self.spec_2t(PType::Queen, PType::Bishop, !us)).zero();
         } else if ty == MType::Castle {
-            let mut ib = between::<true>(k, to);
-            if cap.is_ok() {
-                return false;
-            }
-            while ib.nonzero() {
-                let s = ib.pop_square();
-                if (self.attacks_to(s) & self.color(!us)).nonzero() {
-                    return false;
-                }
-            }
-
-            return true;
+            // Nothing may attack the squares the king crosses, nor where it
+            // lands once the rook is gone, which can uncover a Chess960 king
+            let (king_to, _) = mv.castle_destinations();
+            return between::<false>(k, king_to)
+                .into_iter()
+                .all(|s| !self.is_attacked_by(s, self.all(), !us))
+                && !self.is_attacked_by(king_to, self.all() ^ to, !us);
         }
 
         if from == k {
-            return (self.attacks_to_occ(to, self.all() ^ from) & self.color(!us)).zero();
+            return !self.is_attacked_by(to, self.all() ^ from, !us);
         }
 
-        (self.state().blockers(us) & from).zero() || from.in_line2(to, k)
+        (self.blockers(us) & from).zero() || Square::aligned(from, to, k)
+    }
+
+    #[inline]
+    pub fn do_move(&mut self, mv: Move) -> MoveInfo {
+        self.do_move_with(mv, &mut ())
     }
 
-    pub fn do_move(&mut self, mv: Move) {
+    // do_move, reporting every piece added or removed to `listener`
+    pub fn do_move_with<L: AccumulatorListener>(&mut self, mv: Move, listener: &mut L) -> MoveInfo {
         let from = mv.from();
         let to = mv.to();
         let ty = mv.kind();
@@ -273,85 +591,114 @@ impl Position {
         debug_assert!(mv.is_ok());
         debug_assert!(self.is_legal(mv));
 
+        // A castling move's target is our own rook, which is moved below
+        let cap = if ty == MType::Castle {
+            Piece::NULL
+        } else {
+            self.clear_square(to)
+        };
+        debug_assert!(!cap.is_ok() || cap.color() != us);
+        if cap.is_ok() {
+            listener.piece_removed(to, cap);
+        }
+        let ep_cap = if ty == MType::EnPassant {
+            let ep_cap_sq = Square::create(to.file(), from.rank());
+            let c = self.clear_square(ep_cap_sq);
+            debug_assert_eq!(c, Piece::new(PType::Pawn, !us));
+            listener.piece_removed(ep_cap_sq, c);
+            c
+        } else {
+            Piece::NULL
+        };
+
         let moved = self.clear_square(from);
         debug_assert_ne!(moved, Piece::NULL);
         debug_assert_eq!(moved.color(), us);
-
-        let cap = self.clear_square(to);
-        debug_assert!(!cap.is_ok() || cap.color() != us);
+        listener.piece_removed(from, moved);
         debug_assert!(ty == MType::EnPassant || to != self.state().ep() || !cap.is_ok());
-        debug_assert!(ty != MType::Castle || !cap.is_ok());
-        debug_assert!(ty != MType::Castle || from == E1.relative(us));
 
-        let mut st = self.state.clone();
-        st.captured = cap;
-        st.rule50 += 1;
+        // Both castling pieces are lifted before either lands, in Chess960
+        // the king can land where the rook stood
+        let (dest, rook_to) = if ty == MType::Castle {
+            let rk = self.clear_square(to);
+            debug_assert_eq!(rk, Piece::new(PType::Rook, us));
+            listener.piece_removed(to, rk);
+            mv.castle_destinations()
+        } else {
+            (to, Square::NULL)
+        };
+
+        // Only carry over what survives the move, the check info is rebuilt
+        let mut st = State {
+            castle: self.state.castle,
+            ep: Square::NULL,
+            rule50: self.state.rule50 + 1,
+            captured: cap,
+            key: self.state.key,
+            pawn_key: self.state.pawn_key,
+            ..State::default()
+        };
         self.ply += 1;
-        st.ep = Square::NULL;
+
+        st.key ^= zobrist::color() ^ zobrist::piece(us, moved.kind(), from);
+        if self.state.ep.is_ok() {
+            st.key ^= zobrist::ep_file(self.state.ep.file());
+        }
+        if cap.is_ok() {
+            st.key ^= zobrist::piece(!us, cap.kind(), to);
+            if cap.kind() == PType::Pawn {
+                st.pawn_key ^= zobrist::pawn(!us, to);
+            }
+        }
+        if moved.kind() == PType::Pawn {
+            st.pawn_key ^= zobrist::pawn(us, from);
+            if ty != MType::Promotion {
+                st.pawn_key ^= zobrist::pawn(us, to);
+            }
+        }
 
         if ty != MType::Promotion {
             debug_assert_eq!(mv.promo(), PType::Pawn);
-            self.add_piece(to, moved);
+            self.add_piece(dest, moved);
+            listener.piece_added(dest, moved);
+            st.key ^= zobrist::piece(us, moved.kind(), dest);
         } else {
             let prom = Piece::new(mv.promo(), us);
             debug_assert!(prom.kind() != PType::Pawn && prom.kind() != PType::King);
             self.add_piece(to, prom);
+            listener.piece_added(to, prom);
+            st.key ^= zobrist::piece(us, prom.kind(), to);
         }
 
         if ty == MType::EnPassant {
-            let ep_cap_sq = Color::pawn_push(!us)(Bitboard::from(to)).get_square();
-            let c = self.clear_square(ep_cap_sq);
-            debug_assert_eq!(c, Piece::new(PType::Pawn, !us));
-            st.captured = c;
+            let ep_cap_sq = Square::create(to.file(), from.rank());
+            st.captured = ep_cap;
+            st.key ^= zobrist::piece(!us, PType::Pawn, ep_cap_sq);
+            st.pawn_key ^= zobrist::pawn(!us, ep_cap_sq);
         } else if ty == MType::Castle {
-            let rook_file = if to.file() == File::C {
-                File::A
-            } else {
-                File::H
-            };
-            let rook_dest_file = if to.file() == File::C {
-                File::D
-            } else {
-                File::F
-            };
-            let rook_square = Square::create(rook_file, from.rank());
-            let rk = self.clear_square(rook_square);
-            debug_assert_eq!(rk, Piece::new(PType::Rook, us));
-            self.add_piece(Square::create(rook_dest_file, from.rank()), rk);
+            let rk = Piece::new(PType::Rook, us);
+            self.add_piece(rook_to, rk);
+            listener.piece_added(rook_to, rk);
+            st.key ^=
+                zobrist::piece(us, PType::Rook, to) ^ zobrist::piece(us, PType::Rook, rook_to);
         }
 
         if moved.kind() == PType::King {
             st.castle = Castle(st.castle.inner() & !(3 << (2 * us as usize)));
         }
-
-        if moved.kind() == PType::Rook {
-            match from.relative(us) {
-                A1 if st.castle.castle_for(us).1 => {
-                    st.castle.0 ^= 2 << (2 * us as usize);
-                }
-                H1 if st.castle.castle_for(us).0 => {
-                    st.castle.0 ^= 1 << (2 * us as usize);
-                }
-                _ => (),
-            }
-        }
-
-        // Detect killing Their rooks
-        if cap.is_ok() && cap.kind() == PType::Rook {
-            let bit: u8 = match to.relative(us) {
-                H8 => 1 << (2 * (!us) as u8),
-                A8 => 2 << (2 * (!us) as u8),
-                _ => 0,
-            };
-            st.castle.0 &= !bit;
+        // Moving a castling rook, or having it captured, loses its right
+        if st.castle.inner() != 0 {
+            st.castle.0 &= !(self.castle_rights_on(from) | self.castle_rights_on(to));
         }
 
         if moved.kind() == PType::Pawn && from.dist(to) == 2 {
-            let possible_ep = Color::pawn_push(us)(Bitboard::from(from)).get_square();
+            let possible_ep = unsafe { Square::new((from.inner() + to.inner()) / 2) };
             if (pawn_attack(possible_ep, us) & self.spec(PType::Pawn, !us)).nonzero() {
                 st.ep = possible_ep;
+                st.key ^= zobrist::ep_file(possible_ep.file());
             }
         }
+        st.key ^= zobrist::castle_rights(self.state.castle.inner() ^ st.castle.inner());
 
         if moved.kind() == PType::Pawn || cap.is_ok() {
             st.rule50 = 0;
@@ -361,86 +708,125 @@ impl Position {
         self.state.prev = Some(Arc::new(st));
         self.to_move = !self.to_move;
         self.set_state();
+
+        debug_assert!(!self.is_in_check(!self.to_move), "{mv} left its own king in check:\n{self}");
+        debug_assert_eq!((self.state.key, self.state.pawn_key), self.compute_keys());
+
+        MoveInfo {
+            captured: self.state.captured,
+            gives_check: self.state.checkers.nonzero(),
+        }
     }
+    #[inline]
     pub fn undo_move(&mut self, mv: Move) {
+        self.undo_move_with(mv, &mut ());
+    }
+
+    // undo_move, reporting the changes in the reverse order of do_move_with
+    pub fn undo_move_with<L: AccumulatorListener>(&mut self, mv: Move, listener: &mut L) {
         let from = mv.from();
         let to = mv.to();
         let ty = mv.kind();
         let promo = mv.promo();
         let cap = self.state.captured;
 
-        // Flips state.prev -> state
-        let mut st = None;
-        std::mem::swap(&mut self.state.prev, &mut st);
-        self.state =
-            Arc::try_unwrap(st.unwrap()).expect("Undo-move tried to reset to nonexistent state");
+        // Flips state.prev -> state. A cloned position shares the chain, so
+        // the previous state is copied out rather than unwrapped in that case
+        let prev = self.state.prev.take().expect("Undo-move tried to reset to nonexistent state");
+        self.state = Arc::unwrap_or_clone(prev);
         self.to_move = !self.to_move;
         let us = self.to_move();
 
+        if ty == MType::Castle {
+            let (king_to, rook_to) = mv.castle_destinations();
+            let rk = self.clear_square(rook_to);
+            debug_assert_eq!(rk, Piece::new(PType::Rook, us));
+            listener.piece_removed(rook_to, rk);
+            let king = self.clear_square(king_to);
+            debug_assert_eq!(king, Piece::new(PType::King, us));
+            listener.piece_removed(king_to, king);
+            self.add_piece(to, rk);
+            listener.piece_added(to, rk);
+            self.add_piece(from, king);
+            listener.piece_added(from, king);
+            self.ply -= 1;
+            return;
+        }
+
         let mut moved = self.clear_square(to);
         debug_assert_eq!(moved.color(), us);
+        listener.piece_removed(to, moved);
         if ty == MType::Promotion {
             debug_assert_eq!(moved, Piece::new(promo, us));
             moved = Piece::new(PType::Pawn, us);
         }
         self.add_piece(from, moved);
+        listener.piece_added(from, moved);
         if cap.is_ok() {
             let s = if ty == MType::EnPassant {
-                Color::pawn_push(!us)(Bitboard::from(to)).get_square()
+                Square::create(to.file(), from.rank())
             } else {
                 to
             };
             self.add_piece(s, cap);
-        }
-
-        if ty == MType::Castle {
-            let (rook_on_now, rook_replace_to) = match to.file() {
-                File::G => (File::F, File::H),
-                File::C => (File::D, File::A),
-                _ => panic!("Undoing invalid castle"),
-            };
-            let br = Rank::One.relative(us);
-            let rk = self.clear_square(Square::create(rook_on_now, br));
-            debug_assert_eq!(rk, Piece::new(PType::Rook, us));
-            debug_assert!(!self.piece_on(Square::create(rook_replace_to, br)).is_ok());
-            self.add_piece(Square::create(rook_replace_to, br), rk);
+            listener.piece_added(s, cap);
         }
 
         self.ply -= 1;
     }
 
+    // The search's best move for this position, if the TT has one that is legal here
+    pub fn tt_move(&self) -> Option<Move> {
+        let m = tt::probe(self.state.key)?.best_move;
+        (m.is_ok() && self.is_legal_checked(m)).then_some(m)
+    }
+
+    // Debugging aid: do_move followed by undo_move must restore everything
+    pub fn assert_move_roundtrip(&mut self, m: Move) {
+        let fen = self.fen();
+        let board = self.board;
+        let (key, pawn_key) = (self.state.key.0, self.state.pawn_key.0);
+        let material = self.total_material();
+        let ply = self.ply;
+
+        self.do_move(m);
+        self.undo_move(m);
+
+        assert_eq!(fen, self.fen(), "FEN not restored after {m}");
+        assert_eq!(board, self.board, "Board not restored after {m}");
+        assert_eq!(key, self.state.key.0, "Key not restored after {m}");
+        assert_eq!(pawn_key, self.state.pawn_key.0, "Pawn key not restored after {m}");
+        assert_eq!(material, self.total_material(), "Material not restored after {m}");
+        assert_eq!(ply, self.ply, "Ply not restored after {m}");
+    }
+
     #[allow(non_upper_case_globals)]
     pub fn perft<const Root: bool>(&mut self, depth: usize) -> usize {
-        assert_ne!(depth, 0);
-        let mut nodes = 0;
-        let mut cnt;
-        let is_leaf = depth == 2;
+        if depth == 0 {
+            return 1;
+        }
 
         let mut moves = MoveList::new();
         // Say 'false' here to skip a clear
         generate_legal::<false>(self, &mut moves);
 
-        // TODO Iterate properly
-        let l = moves.len();
-        for i in 0..l {
+        // Bulk-count the leaves unless the root wants per-move output
+        if depth == 1 && !Root {
+            return moves.len();
+        }
+
+        let mut nodes = 0;
+        for i in 0..moves.len() {
             let m = moves.get(i);
-            if Root && depth == 1 {
-                cnt = 1;
-                nodes += 1;
+            let cnt = if depth == 1 {
+                1
             } else {
                 self.do_move(m);
-
-                cnt = if is_leaf {
-                    let mut ml = MoveList::new();
-                    generate_legal::<true>(self, &mut ml);
-                    ml.len()
-                } else {
-                    self.perft::<false>(depth - 1)
-                };
-                nodes += cnt;
-
+                let cnt = self.perft::<false>(depth - 1);
                 self.undo_move(m);
-            }
+                cnt
+            };
+            nodes += cnt;
 
             if Root {
                 println!("{m}: {cnt}");
@@ -450,150 +836,696 @@ impl Position {
         nodes
     }
 
-    #[inline]
-    pub fn piece_on(&self, square: Square) -> Piece {
-        self.board[square.inner() as usize]
-    }
-    #[inline]
-    pub fn is_empty_square(&self, square: Square) -> bool {
-        self.piece_on(square).inner() == Piece::NULL.inner()
+    // Perft that gives up once `max_nodes` leaves are counted.
+    // The flag is true when the count was truncated.
+    pub fn perft_capped(&mut self, depth: usize, max_nodes: usize) -> (usize, bool) {
+        let mut nodes = 0;
+        let complete = self.perft_capped_internal(depth, max_nodes, &mut nodes);
+        (nodes, !complete)
     }
 
-    pub fn material(&self, color: Color) -> i32 {
-        let p = self.spec(PType::Pawn, color).popcnt() as i32;
-        let k = self.spec(PType::Knight, color).popcnt() as i32;
-        let b = self.spec(PType::Bishop, color).popcnt() as i32;
-        let r = self.spec(PType::Rook, color).popcnt() as i32;
-        let q = self.spec(PType::Queen, color).popcnt() as i32;
+    fn perft_capped_internal(&mut self, depth: usize, max_nodes: usize, nodes: &mut usize) -> bool {
+        if depth == 0 {
+            if *nodes >= max_nodes {
+                return false;
+            }
+            *nodes += 1;
+            return true;
+        }
 
-        p * PType::Pawn.value()
-            + k * PType::Knight.value()
-            + b * PType::Bishop.value()
-            + r * PType::Rook.value()
-            + q * PType::Queen.value()
-    }
+        let mut moves = MoveList::new();
+        generate_legal::<false>(self, &mut moves);
 
-    // FIXME This is the biggest slowdown at the moment.
-    // Maybe move zobrist-stuff out?
-    fn compute_check_info(&mut self) {
-        let us = self.to_move();
-        let king = self.king(us);
-        if (self.attacks_to(self.king(!us)) & self.color(us)).nonzero() {
-            println!("{self}");
+        if depth == 1 {
+            if *nodes + moves.len() > max_nodes {
+                *nodes = max_nodes;
+                return false;
+            }
+            *nodes += moves.len();
+            return true;
         }
-        debug_assert_eq!(
-            self.attacks_to(self.king(!us)) & self.color(us),
-            Bitboard::ZERO
-        );
-        self.state.pinners[0] = Bitboard::ZERO;
-        self.state.pinners[1] = Bitboard::ZERO;
-        self.state.blockers[0] = Bitboard::ZERO;
-        self.state.blockers[1] = Bitboard::ZERO;
-
-        self.state.check_squares[0] = pawn_attack(king, us);
-        self.state.check_squares[1] = knight_attack(king);
-        self.state.check_squares[2] = bishop_moves(king, Bitboard::ZERO);
-        self.state.check_squares[3] = rook_moves(king, Bitboard::ZERO);
-        self.state.check_squares[4] = self.state.check_squares[2] | self.state.check_squares[3];
-        self.state.check_squares[5] = if (self.state.blockers(us) & self.king(!us)).nonzero() {
-            king_attack(self.king(!us))
-        } else {
-            Bitboard::ZERO
-        };
 
-        for col in [White, Black] {
-            let king = self.king(col);
-            let (blockers, pinners) = self.slider_blockers(self.color(!col), king);
-            self.state.blockers[col as usize] = blockers;
-            self.state.pinners[!col as usize] = pinners;
+        for i in 0..moves.len() {
+            let m = moves.get(i);
+            self.do_move(m);
+            let complete = self.perft_capped_internal(depth - 1, max_nodes, nodes);
+            self.undo_move(m);
+            if !complete {
+                return false;
+            }
         }
-    }
 
-    fn set_state(&mut self) {
-        self.state.checkers =
-            self.attacks_to(self.king(self.to_move())) & self.color(!self.to_move());
-        self.compute_check_info();
+        true
     }
 
-    pub fn fen(&self) -> String {
-        let mut fen = String::with_capacity(92);
+    // Decodes a UCI move such as e2e4 or e7e8q, using the board to tell
+    // castling and en passant apart. Castling is read both as e1g1 and as
+    // king takes rook, the Chess960 form. Legality is not checked.
+    pub fn parse_uci_move(&self, s: &str) -> Result<Move, &'static str> {
+        let b = s.as_bytes();
+        if !(4..=5).contains(&b.len()) {
+            return Err("Malformed move");
+        }
 
-        macro_rules! f {
-            () => {
-                fen.push(' ');
+        let square = |f: u8, r: u8| -> Option<Square> {
+            let file = File::try_from(f as char).ok()?;
+            let rank = Rank::try_from(r as char).ok()?;
+            Some(Square::create(file, rank))
+        };
+        let (Some(from), Some(to)) = (square(b[0], b[1]), square(b[2], b[3])) else {
+            return Err("Invalid square");
+        };
+        if let Some(&c) = b.get(4) {
+            return match PType::try_from(c as char) {
+                Ok(PType::Pawn | PType::King) | Err(_) => Err("Invalid promotion piece"),
+                Ok(pt) => Ok(Move::promotion(from, to, pt)),
             };
         }
 
-        for i in 0..8 {
-            let mut empty = 0;
-            for j in 0..8 {
-                let s = unsafe { Square::new((7 - i) * 8 + j) };
+        Ok(self.retype(Move::new(from, to)))
+    }
+
+    // Safe on arbitrary moves, unlike is_legal which expects pseudo-legal input
+    pub fn is_legal_checked(&self, m: Move) -> bool {
+        self.is_pseudo_legal(m) && self.is_legal(m)
+    }
+
+    // Plays a space-separated list of UCI moves, as sent by `position ... moves`.
+    // On failure, reports the index of the offending move; earlier moves stay played.
+    pub fn apply_uci_moves(&mut self, moves: &str) -> Result<(), (usize, &'static str)> {
+        for (i, s) in moves.split_whitespace().enumerate() {
+            let m = self.parse_uci_move(s).map_err(|e| (i, e))?;
+            if !self.is_legal_checked(m) {
+                return Err((i, "Illegal move"));
+            }
+            self.do_move(m);
+        }
+        Ok(())
+    }
+
+    // A FEN (clocks optional) followed by UCI moves, with or without a
+    // `moves` keyword in between, e.g. "<fen> 0 1 moves e2e4 e7e5"
+    pub fn from_fen_and_moves(s: &str) -> Result<Self, &'static str> {
+        let tokens = s.split_whitespace().collect::<Vec<_>>();
+        if tokens.len() < 4 {
+            return Err("FEN needs at least four fields");
+        }
+        let clocks = tokens[4..]
+            .iter()
+            .take(2)
+            .take_while(|t| t.parse::<u32>().is_ok())
+            .count();
+        let (fen, mut rest) = tokens.split_at(4 + clocks);
+        if rest.first() == Some(&"moves") {
+            rest = &rest[1..];
+        }
+
+        let mut pos = Self::from_str(&fen.join(" "))?;
+        pos.apply_uci_moves(&rest.join(" ")).map_err(|(_, e)| e)?;
+        Ok(pos)
+    }
+
+    // Long algebraic notation, e.g. Ng1-f3, e7xd8=Q or Rf1xf7+
+    pub fn to_lan(&self, m: Move) -> String {
+        let check = if self.gives_check(m) { "+" } else { "" };
+        if m.kind() == MType::Castle {
+            let side = if m.to() > m.from() { "O-O" } else { "O-O-O" };
+            return format!("{side}{check}");
+        }
+
+        let moved = self.piece_on(m.from()).kind();
+        let piece = match moved {
+            PType::Pawn => String::new(),
+            pt => char::from(pt).to_ascii_uppercase().to_string(),
+        };
+        let sep = if self.is_capture(m) { 'x' } else { '-' };
+        let promo = match m.kind() {
+            MType::Promotion => format!("={}", char::from(m.promo()).to_ascii_uppercase()),
+            _ => String::new(),
+        };
+
+        format!("{piece}{}{sep}{}{promo}{check}", m.from(), m.to())
+    }
+
+    // Whether `a` and `b` are the same move here. Both are typed from the
+    // board first, so a coordinate-only move matches its fully tagged
+    // generated twin, whatever kind either one claims to be.
+    pub fn moves_equivalent(&self, a: Move, b: Move) -> bool {
+        self.retype(a) == self.retype(b)
+    }
+
+    // `m` encoded the way movegen would here, judging its kind by the board
+    // rather than its tag. A promotion keeps its piece only if it names one.
+    fn retype(&self, m: Move) -> Move {
+        let (from, to) = (m.from(), m.to());
+        let piece = self.piece_on(from);
+        let two_across = from.rank() == to.rank() && from.inner().abs_diff(to.inner()) == 2;
+        match piece.kind_opt() {
+            Some(PType::Pawn) if to.relative_rank(piece.color()) == Rank::Eight => {
+                if m.kind() == MType::Promotion {
+                    Move::new(from, to).add_promo(m.promo())
+                } else {
+                    Move::new(from, to)
+                }
+            }
+            Some(PType::Pawn) if from.file() != to.file() && to == self.state.ep => {
+                Move::en_passant(from, to)
+            }
+            // Only castling puts the king on its own rook
+            Some(PType::King) if self.piece_on(to) == Piece::new(PType::Rook, piece.color()) => {
+                Move::castle(from, to)
+            }
+            Some(PType::King) if two_across && from.file() == File::E => {
+                Move::castle(from, self.castling_rook(piece.color(), to.file() == File::G))
+            }
+            _ => Move::new(from, to),
+        }
+    }
+
+    // Standard algebraic notation, e.g. Nbd7, exd5, e8=Q+ or Qh4#. The
+    // suffix comes from playing the move, so mate is told apart from check.
+    pub fn san(&self, m: Move) -> String {
+        let mut after = self.clone();
+        after.do_move(m);
+        let suffix = match (after.in_check(), after.legal_moves().is_empty()) {
+            (true, true) => "#",
+            (true, false) => "+",
+            _ => "",
+        };
+
+        if m.kind() == MType::Castle {
+            let side = if m.to() > m.from() { "O-O" } else { "O-O-O" };
+            return format!("{side}{suffix}");
+        }
+
+        let moved = self.piece_on(m.from()).kind();
+        let capture = self.is_capture(m);
+        let mut san = String::with_capacity(8);
+
+        if moved == PType::Pawn {
+            // Pawn captures always name the file they come from, which is
+            // also all it takes when two pawns can capture on one square
+            if capture {
+                san.push(char::from(m.from().file()));
+            }
+        } else {
+            san.push(char::from(moved).to_ascii_uppercase());
+            let others = self
+                .legal_moves()
+                .into_iter()
+                .filter(|o| o.to() == m.to() && o.from() != m.from())
+                .filter(|o| self.piece_on(o.from()).kind() == moved)
+                .map(|o| o.from())
+                .collect::<Vec<_>>();
+            if !others.is_empty() {
+                let file = m.from().file();
+                let rank = m.from().rank();
+                if others.iter().all(|s| s.file() != file) {
+                    san.push(char::from(file));
+                } else if others.iter().all(|s| s.rank() != rank) {
+                    san.push(char::from(rank));
+                } else {
+                    san.push_str(&m.from().to_string());
+                }
+            }
+        }
+
+        if capture {
+            san.push('x');
+        }
+        san.push_str(&m.to().to_string());
+        if m.kind() == MType::Promotion {
+            san.push('=');
+            san.push(char::from(m.promo()).to_ascii_uppercase());
+        }
+        san.push_str(suffix);
+        san
+    }
+
+    // Castling lands on our own rook, so the target square alone can't tell
+    #[inline]
+    pub fn is_capture(&self, m: Move) -> bool {
+        match m.kind() {
+            MType::EnPassant => true,
+            MType::Castle => false,
+            _ => self.piece_on(m.to()).is_ok(),
+        }
+    }
+
+    // The value of whatever `m` captures, 0 for non-captures
+    #[inline]
+    pub fn capture_value(&self, m: Move) -> i32 {
+        match m.kind() {
+            MType::EnPassant => PType::Pawn.value(),
+            MType::Castle => 0,
+            _ => self.piece_on(m.to()).kind_opt().map_or(0, PType::value),
+        }
+    }
+
+    // Static exchange evaluation: the material balance for the side to
+    // move after the full sequence of captures on the target square,
+    // always recapturing with the least valuable piece. Pins are ignored.
+    pub fn see(&self, m: Move) -> i32 {
+        const fn see_value(pt: PType) -> i32 {
+            match pt {
+                PType::King => 20_000,
+                _ => pt.value(),
+            }
+        }
+
+        let from = m.from();
+        let to = m.to();
+        let captured = match m.kind() {
+            MType::EnPassant => PType::Pawn.value(),
+            MType::Castle => return 0,
+            _ => self.piece_on(to).kind_opt().map_or(0, PType::value),
+        };
+
+        let mut gain = [0; 32];
+        let mut d = 0;
+        gain[0] = captured;
+
+        let mut occ = self.all() ^ from;
+        if m.kind() == MType::EnPassant {
+            occ ^= Square::create(to.file(), from.rank());
+        }
+        let mut attackers = self.attacks_to_occ(to, occ) & occ;
+        let mut last = if m.kind() == MType::Promotion {
+            m.promo()
+        } else {
+            self.piece_on(from).kind()
+        };
+        let mut side = !self.to_move();
+
+        loop {
+            let ours = attackers & self.color(side);
+            let Some(pt) = [
+                PType::Pawn,
+                PType::Knight,
+                PType::Bishop,
+                PType::Rook,
+                PType::Queen,
+                PType::King,
+            ]
+            .into_iter()
+            .find(|&pt| (ours & self.piece(pt)).nonzero()) else {
+                break;
+            };
+
+            d += 1;
+            gain[d] = see_value(last) - gain[d - 1];
+            last = pt;
+
+            let sq = (ours & self.piece(pt)).get_square();
+            occ ^= sq;
+            // Sliders behind the capturer join in
+            attackers |= (bishop_moves(to, occ) & self.piece_2t(PType::Bishop, PType::Queen))
+                | (rook_moves(to, occ) & self.piece_2t(PType::Rook, PType::Queen));
+            attackers &= occ;
+            side = !side;
+        }
+
+        while d > 0 {
+            gain[d - 1] = -(-gain[d - 1]).max(gain[d]);
+            d -= 1;
+        }
+
+        gain[0]
+    }
+
+    // Generates the legal moves into `list` and reports whether the game is over
+    pub fn status_with_moves(&self, list: &mut MoveList) -> GameResult {
+        generate_legal::<true>(self, list);
+        if !list.is_empty() {
+            GameResult::Ongoing
+        } else if self.in_check() {
+            GameResult::Checkmate
+        } else {
+            GameResult::Stalemate
+        }
+    }
+
+    // How often the current position has occurred, itself included. Only
+    // positions since the last capture or pawn move can be repeats.
+    pub fn repetition_count(&self) -> usize {
+        let key = self.state.key;
+        let mut count = 1;
+        let mut back = 0;
+        let mut st = self.state.prev.as_deref();
+
+        while let Some(s) = st {
+            back += 1;
+            if back > self.state.rule50 {
+                break;
+            }
+            if back % 2 == 0 && s.key == key {
+                count += 1;
+            }
+            st = s.prev.as_deref();
+        }
+        count
+    }
+
+    // A draw either side may claim
+    #[inline]
+    pub fn threefold_repetition(&self) -> bool {
+        self.repetition_count() >= 3
+    }
+
+    // A draw without anyone claiming it (FIDE 9.6.1)
+    #[inline]
+    pub fn fivefold_repetition(&self) -> bool {
+        self.repetition_count() >= 5
+    }
+
+    #[inline]
+    pub fn piece_on(&self, square: Square) -> Piece {
+        self.board[square.inner() as usize]
+    }
+    #[inline]
+    pub fn is_empty_square(&self, square: Square) -> bool {
+        self.piece_on(square).inner() == Piece::NULL.inner()
+    }
+
+    // (White, Black) material, kings not counted
+    pub fn total_material(&self) -> (i32, i32) {
+        let (white, black) = (self.color(White), self.color(Black));
+        let mut total = (0, 0);
+        for pt in [PType::Pawn, PType::Knight, PType::Bishop, PType::Rook, PType::Queen] {
+            let bb = self.piece(pt);
+            total.0 += (bb & white).popcnt() as i32 * pt.value();
+            total.1 += (bb & black).popcnt() as i32 * pt.value();
+        }
+        total
+    }
+
+    pub fn material(&self, color: Color) -> i32 {
+        let p = self.spec(PType::Pawn, color).popcnt() as i32;
+        let k = self.spec(PType::Knight, color).popcnt() as i32;
+        let b = self.spec(PType::Bishop, color).popcnt() as i32;
+        let r = self.spec(PType::Rook, color).popcnt() as i32;
+        let q = self.spec(PType::Queen, color).popcnt() as i32;
+
+        p * PType::Pawn.value()
+            + k * PType::Knight.value()
+            + b * PType::Bishop.value()
+            + r * PType::Rook.value()
+            + q * PType::Queen.value()
+    }
+
+    // Only the side to move's pins, the rest waits for check_info
+    fn compute_check_info(&mut self) {
+        let us = self.to_move();
+        let (blockers, pinners) = self.slider_blockers(self.color(!us), self.king(us));
+        self.state.blockers = blockers;
+        self.state.pinners = pinners;
+        self.state.check_info = OnceLock::new();
+    }
+
+    fn check_info(&self) -> CheckInfo {
+        *self.state.check_info.get_or_init(|| self.compute_their_check_info())
+    }
+
+    fn compute_their_check_info(&self) -> CheckInfo {
+        let us = self.to_move();
+        // Check squares are the squares our pieces give check from
+        let king = self.king(!us);
+        let bishop = bishop_moves(king, self.all());
+        let rook = rook_moves(king, self.all());
+        let (blockers, pinners) = self.slider_blockers(self.color(us), king);
+        CheckInfo {
+            check_squares: [
+                pawn_attack(king, !us),
+                knight_attack(king),
+                bishop,
+                rook,
+                bishop | rook,
+                Bitboard::ZERO,
+            ],
+            blockers,
+            pinners,
+        }
+    }
+
+    // Squares a piece of type `ty` would check the enemy king from
+    #[inline]
+    pub fn check_squares(&self, ty: PType) -> Bitboard {
+        self.check_info().check_squares[ty as usize]
+    }
+
+    // Pieces (of either color) standing between `color`'s king and an enemy slider
+    pub fn blockers(&self, color: Color) -> Bitboard {
+        if color == self.to_move() {
+            self.state.blockers
+        } else {
+            self.check_info().blockers
+        }
+    }
+
+    // Sliders of `color` pinning something to the enemy king
+    pub fn pinners(&self, color: Color) -> Bitboard {
+        if color == self.to_move() {
+            self.check_info().pinners
+        } else {
+            self.state.pinners
+        }
+    }
+
+    // Whether the piece on `square` is pinned to its own king
+    pub fn is_pinned(&self, square: Square) -> bool {
+        let p = self.piece_on(square);
+        p.is_ok() && p.kind() != PType::King && self.blockers(p.color()).contains(square)
+    }
+
+    // The (file, rank) step from the king towards the pinned piece on
+    // `square`, the only line it may move along
+    pub fn pinned_direction(&self, square: Square) -> Option<(i32, i32)> {
+        if !self.is_pinned(square) {
+            return None;
+        }
+        let king = self.king(self.piece_on(square).color());
+        let df = square.file() as i32 - king.file() as i32;
+        let dr = square.rank() as i32 - king.rank() as i32;
+        Some((df.signum(), dr.signum()))
+    }
+
+    // Hashes the position from scratch, do_move keeps these up to date
+    fn compute_keys(&self) -> (Key, Key) {
+        let mut key = zobrist::castle_rights(self.state.castle.inner());
+        let mut pawn_key = zobrist::no_pawns();
+
+        for s in self.all() {
+            let p = self.piece_on(s);
+            key ^= zobrist::piece(p.color(), p.kind(), s);
+            if p.kind() == PType::Pawn {
+                pawn_key ^= zobrist::pawn(p.color(), s);
+            }
+        }
+        if self.state.ep.is_ok() {
+            key ^= zobrist::ep_file(self.state.ep.file());
+        }
+        if self.to_move == Black {
+            key ^= zobrist::color();
+        }
+
+        (key, pawn_key)
+    }
+
+    fn set_state(&mut self) {
+        self.state.checkers =
+            self.attackers_by(self.king(self.to_move()), self.all(), !self.to_move());
+        self.compute_check_info();
+    }
+
+    pub fn fen(&self) -> String {
+        let mut fen = String::with_capacity(92);
+        self.write_fen(&mut fen).expect("writing to a String can't fail");
+        fen
+    }
+
+    // fen() without the allocation, for dumping many positions into one buffer
+    pub fn write_fen(&self, w: &mut impl fmt::Write) -> fmt::Result {
+        for i in 0..8 {
+            let mut empty = 0;
+            for j in 0..8 {
+                let s = unsafe { Square::new((7 - i) * 8 + j) };
                 debug_assert!(s.is_ok());
                 let p = self.piece_on(s);
 
                 if p.is_ok() {
                     if empty > 0 {
-                        fen.push((b'0' + empty) as char);
+                        w.write_char((b'0' + empty) as char)?;
                         empty = 0;
                     }
-                    fen.push(char::from(p));
+                    w.write_char(char::from(p))?;
                 } else {
                     empty += 1;
                 }
             }
             if empty > 0 {
-                fen.push((b'0' + empty) as char);
+                w.write_char((b'0' + empty) as char)?;
             }
             if i != 7 {
-                fen.push('/');
+                w.write_char('/')?;
             }
         }
 
-        f!();
-        fen.push(match self.to_move {
-            White => 'w',
-            _ => 'b',
-        });
-        f!();
+        write!(w, " {} ", self.to_move.to_fen_char())?;
 
         if self.state.castle.0 == 0 {
-            fen.push('-');
+            w.write_char('-')?;
         } else {
-            let (wk, wq) = self.state.cur_castle().castle_for(White);
-            let (bk, bq) = self.state.cur_castle().castle_for(Black);
-
-            if wk {
-                fen.push('K');
+            // X-FEN: KQkq unless another rook stands further out than the
+            // castling one, then the castling rook's file
+            for (c, king_side, letter) in
+                [(White, true, 'K'), (White, false, 'Q'), (Black, true, 'k'), (Black, false, 'q')]
+            {
+                if self.state.castle.inner() & Castle::bit(c, king_side) == 0 {
+                    continue;
+                }
+                let rook = self.castling_rook(c, king_side);
+                let letter = match self.outer_rook(c, king_side) {
+                    Some(outer) if outer != rook => {
+                        let file = char::from(rook.file());
+                        if c == White { file.to_ascii_uppercase() } else { file }
+                    }
+                    _ => letter,
+                };
+                w.write_char(letter)?;
             }
-            if wq {
-                fen.push('Q');
+        }
+        w.write_char(' ')?;
+
+        if self.state.ep.is_ok() {
+            write!(w, "{}", self.state.ep)?;
+        } else {
+            w.write_char('-')?;
+        }
+
+        write!(w, " {} {}", self.state.rule50, self.ply / 2 + 1)
+    }
+
+    // EPD: the first four FEN fields followed by `opcode operand;` pairs.
+    // Operands with spaces and `id`/comment strings are quoted.
+    pub fn to_epd(&self, ops: &[(&str, &str)]) -> String {
+        let mut epd = self.fen().split(' ').take(4).collect::<Vec<_>>().join(" ");
+
+        for &(op, operand) in ops {
+            let quote = op == "id"
+                || (op.len() == 2 && op.starts_with('c'))
+                || operand.contains(char::is_whitespace);
+            if quote {
+                epd.push_str(&format!(" {op} \"{operand}\";"));
+            } else {
+                epd.push_str(&format!(" {op} {operand};"));
             }
-            if bk {
-                fen.push('k');
+        }
+
+        epd
+    }
+
+    // Reads an EPD line back into the position and its operations.
+    // A `;` inside a quoted operand doesn't end the operation.
+    pub fn from_epd(epd: &str) -> EpdResult {
+        let mut rest = epd.trim();
+        let mut fen = Vec::with_capacity(4);
+        for _ in 0..4 {
+            let (field, tail) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            if field.is_empty() {
+                return Err("EPD needs four position fields");
             }
-            if bq {
-                fen.push('q');
+            fen.push(field);
+            rest = tail.trim_start();
+        }
+        let pos = Self::from_str(&fen.join(" "))?;
+
+        let mut ops = Vec::new();
+        let mut op = String::new();
+        let mut quoted = false;
+        for c in rest.chars() {
+            match c {
+                '"' => {
+                    quoted = !quoted;
+                    op.push(c);
+                }
+                ';' if !quoted => {
+                    push_epd_op(&mut ops, &op);
+                    op.clear();
+                }
+                _ => op.push(c),
             }
         }
-        f!();
+        if quoted {
+            return Err("Unterminated EPD string");
+        }
+        push_epd_op(&mut ops, &op);
 
-        if self.state.ep.is_ok() {
-            fen.push_str(&self.state.ep.to_string());
+        Ok((pos, ops))
+    }
+
+    // Shredder-FEN/X-FEN castling: the letter names the file of the castling
+    // rook, which decides the side by where it stands from the king
+    fn shredder_castle_bit(&mut self, c: char) -> Result<u8, &'static str> {
+        let color = if c.is_ascii_uppercase() { White } else { Black };
+        let file = File::try_from(c.to_ascii_lowercase()).map_err(|_| "Invalid castling file")?;
+        let back_rank = Rank::One.relative(color);
+
+        let king = self.king(color);
+        if king.relative_rank(color) != Rank::One {
+            return Err("No king on the back rank for castling");
+        }
+        if self.piece_on(Square::create(file, back_rank)) != Piece::new(PType::Rook, color) {
+            return Err("No rook on the given castling file");
+        }
+        if file == king.file() {
+            return Err("Invalid castling file");
+        }
+
+        let king_side = file > king.file();
+        self.castling_rooks[color as usize][!king_side as usize] = file;
+        Ok(Castle::bit(color, king_side))
+    }
+
+    // KQkq in X-FEN mean the outermost rook on that side of the king. With
+    // none, the standard file stays so that validate reports the bad right.
+    fn outer_castle_bit(&mut self, color: Color, king_side: bool) -> u8 {
+        if let Some(rook) = self.outer_rook(color, king_side) {
+            self.castling_rooks[color as usize][!king_side as usize] = rook.file();
+        }
+        Castle::bit(color, king_side)
+    }
+
+    // The rook on `color`'s back rank furthest from its king on that side
+    fn outer_rook(&self, color: Color, king_side: bool) -> Option<Square> {
+        let king = self.king(color);
+        let rooks = self.spec(PType::Rook, color) & Bitboard::from(Rank::One.relative(color));
+        let mut beyond = rooks.into_iter().filter(|r| r.rank() == king.rank());
+        if king_side {
+            beyond.filter(|r| r.file() > king.file()).max()
         } else {
-            fen.push('-');
+            beyond.find(|r| r.file() < king.file())
         }
+    }
+}
 
-        // FIXME Add the halfmove and plies to the FEN
+pub type EpdResult = Result<(Position, Vec<(String, String)>), &'static str>;
 
-        fen
+// Quoted operands are kept as written, others have their spacing collapsed
+fn push_epd_op(ops: &mut Vec<(String, String)>, op: &str) {
+    let op = op.trim();
+    if op.is_empty() {
+        return;
     }
+    let (code, operand) = op.split_once(char::is_whitespace).unwrap_or((op, ""));
+    let operand = operand.trim();
+    let operand = match operand.strip_prefix('"').and_then(|o| o.strip_suffix('"')) {
+        Some(inner) => inner.to_string(),
+        None => operand.split_whitespace().collect::<Vec<_>>().join(" "),
+    };
+    ops.push((code.to_string(), operand));
 }
 
 impl State {
-    #[inline]
-    pub const fn check_squares(&self, ty: PType) -> Bitboard {
-        self.check_squares[ty as usize]
-    }
     #[inline]
     pub const fn ep(&self) -> Square {
         self.ep
@@ -613,301 +1545,1619 @@ impl State {
         self.checkers
     }
     #[inline]
-    pub const fn blockers(&self, color: Color) -> Bitboard {
-        self.blockers[color as usize]
+    pub const fn key(&self) -> Key {
+        self.key
+    }
+
+    #[inline]
+    pub const fn pawn_key(&self) -> Key {
+        self.pawn_key
+    }
+}
+
+impl Castle {
+    #[inline]
+    const fn inner(self) -> u8 {
+        self.0
+    }
+
+    // K, Q, k and q are the bits 1, 2, 4 and 8
+    #[inline]
+    const fn bit(color: Color, king_side: bool) -> u8 {
+        (if king_side { 1 } else { 2 }) << (2 * color as u8)
     }
+
     #[inline]
-    pub const fn pinners(&self, color: Color) -> Bitboard {
-        self.pinners[color as usize]
+    pub const fn castle_for(self, color: Color) -> (bool, bool) {
+        let king = match color {
+            White => 1,
+            Black => 4,
+        };
+        let queen = match color {
+            White => 2,
+            Black => 8,
+        };
+        let c = self.inner();
+        (c & king > 0, c & queen > 0)
+    }
+}
+
+impl Position {
+    // The standard initial position, built without parsing a FEN
+    pub fn startpos() -> Self {
+        use PType::*;
+        let mut p = Self::default();
+        let back_rank = [Rook, Knight, Bishop, Queen, King, Bishop, Knight, Rook];
+
+        for (f, pt) in back_rank.into_iter().enumerate() {
+            let file = File::from(f as u8);
+            for c in [White, Black] {
+                p.add_piece(Square::create(file, Rank::One.relative(c)), Piece::new(pt, c));
+                p.add_piece(Square::create(file, Rank::Two.relative(c)), Piece::new(Pawn, c));
+            }
+        }
+        p.state.castle = Castle(0b1111);
+
+        p.finish_setup();
+        p
+    }
+
+    // "Kiwipete", the usual perft stress test position
+    pub fn kiwipete() -> Self {
+        use PType::*;
+        let mut p = Self::default();
+        let white = [
+            (A1, Rook), (E1, King), (H1, Rook),
+            (A2, Pawn), (B2, Pawn), (C2, Pawn), (D2, Bishop),
+            (E2, Bishop), (F2, Pawn), (G2, Pawn), (H2, Pawn),
+            (C3, Knight), (F3, Queen), (E4, Pawn), (D5, Pawn), (E5, Knight),
+        ];
+        let black = [
+            (A8, Rook), (E8, King), (H8, Rook),
+            (A7, Pawn), (C7, Pawn), (D7, Pawn), (E7, Queen), (F7, Pawn), (G7, Bishop),
+            (A6, Bishop), (B6, Knight), (E6, Pawn), (F6, Knight), (G6, Pawn),
+            (B4, Pawn), (H3, Pawn),
+        ];
+
+        for (sq, pt) in white {
+            p.add_piece(sq, Piece::new(pt, White));
+        }
+        for (sq, pt) in black {
+            p.add_piece(sq, Piece::new(pt, Black));
+        }
+        p.state.castle = Castle(0b1111);
+
+        p.finish_setup();
+        p
+    }
+
+    // Once the pieces, side and rights are in place
+    fn finish_setup(&mut self) {
+        self.set_state();
+        self.normalize_ep();
+        (self.state.key, self.state.pawn_key) = self.compute_keys();
     }
 
-    #[inline]
-    pub const fn key(&self) -> Key {
-        self.key
+    // Programmatic setup. None of these update the check info or keys,
+    // so call recompute_state once the position is complete.
+    pub fn set_piece(&mut self, square: Square, piece: Piece) {
+        self.clear_square(square);
+        if piece.is_ok() {
+            self.add_piece(square, piece);
+        }
+    }
+
+    pub fn set_to_move(&mut self, color: Color) {
+        self.ply += color as i32 - self.to_move as i32;
+        self.to_move = color;
+    }
+
+    pub fn set_halfmove_clock(&mut self, rule50: i32) {
+        self.state.rule50 = rule50;
+    }
+
+    // Relative to the side to move, so set that first
+    pub fn set_fullmove(&mut self, fullmove: u32) -> Result<(), &'static str> {
+        let ply = i32::try_from(fullmove.max(1) - 1)
+            .ok()
+            .and_then(|n| n.checked_mul(2))
+            .and_then(|n| n.checked_add(self.to_move as i32))
+            .ok_or("Invalid fullmove number")?;
+        self.ply = ply;
+        Ok(())
+    }
+
+    pub fn set_en_passant(&mut self, square: Option<Square>) -> Result<(), &'static str> {
+        match square {
+            None => self.state.ep = Square::NULL,
+            Some(s) if s.relative_rank(self.to_move) == Rank::Six => self.state.ep = s,
+            Some(_) => return Err("En passant square is on the wrong rank"),
+        }
+        Ok(())
+    }
+
+    // Drop an ep square nothing can capture on, the same test do_move
+    // uses, so a parsed position hashes like the one reached by moves
+    fn normalize_ep(&mut self) {
+        let ep = self.state.ep;
+        if !ep.is_ok() {
+            return;
+        }
+        let us = self.to_move;
+        if ep.relative_rank(us) != Rank::Six {
+            self.state.ep = Square::NULL;
+            return;
+        }
+        let pushed = ep.relative(us).inner() - 8;
+        let pushed = unsafe { Square::new(pushed) }.relative(us);
+        if self.piece_on(pushed) != Piece::new(PType::Pawn, !us)
+            || (pawn_attack(ep, !us) & self.spec(PType::Pawn, us)).zero()
+        {
+            self.state.ep = Square::NULL;
+        }
+    }
+
+    pub fn en_passant(&self) -> Option<Square> {
+        self.state.ep.is_ok().then_some(self.state.ep)
+    }
+
+    pub fn recompute_state(&mut self) {
+        self.finish_setup();
+    }
+
+    // The same position with the board mirrored top to bottom and the
+    // colors swapped, so Black stands where White did
+    pub fn flipped(&self) -> Self {
+        let mut p = Self::default();
+        for s in self.all() {
+            let pc = self.piece_on(s);
+            p.add_piece(s.relative(Black), Piece::new(pc.kind(), !pc.color()));
+        }
+        let c = self.state.castle.inner();
+        p.state.castle = Castle(((c & 3) << 2) | (c >> 2));
+        p.castling_rooks = [self.castling_rooks[1], self.castling_rooks[0]];
+        if self.state.ep.is_ok() {
+            p.state.ep = self.state.ep.relative(Black);
+        }
+        p.state.rule50 = self.state.rule50;
+        p.to_move = !self.to_move;
+        p.ply = self.ply - self.to_move as i32 + p.to_move as i32;
+        p.finish_setup();
+        p
+    }
+
+    // Everything a hand-built or parsed position could get wrong that
+    // movegen and search take for granted
+    pub fn validate(&self) -> Result<(), PositionError> {
+        for c in [White, Black] {
+            if self.spec(PType::King, c).popcnt() != 1 {
+                return Err(PositionError::KingCount(c));
+            }
+        }
+        if self.is_in_check(!self.to_move) {
+            return Err(PositionError::OpponentInCheck);
+        }
+
+        let back_ranks = Bitboard::from(Rank::One) | Rank::Eight;
+        if let Some(s) = (self.piece(PType::Pawn) & back_ranks).first_square() {
+            return Err(PositionError::PawnOnBackRank(s));
+        }
+
+        let ep = self.state.ep;
+        if ep.is_ok() {
+            let us = self.to_move;
+            let pushed = Square::create(ep.file(), Rank::Five.relative(us));
+            let origin = Square::create(ep.file(), Rank::Seven.relative(us));
+            if ep.relative_rank(us) != Rank::Six
+                || self.piece_on(pushed) != Piece::new(PType::Pawn, !us)
+                || (self.all() & (Bitboard::from(ep) | origin)).nonzero()
+            {
+                return Err(PositionError::BadEnPassant(ep));
+            }
+        }
+
+        // Each right needs the king on its back rank and the castling rook
+        // on the matching side of it
+        for c in [White, Black] {
+            let king = self.king(c);
+            for king_side in [true, false] {
+                if self.state.castle.inner() & Castle::bit(c, king_side) == 0 {
+                    continue;
+                }
+                let rook = self.castling_rook(c, king_side);
+                if king.relative_rank(c) != Rank::One
+                    || self.piece_on(rook) != Piece::new(PType::Rook, c)
+                    || (rook.file() > king.file()) != king_side
+                {
+                    return Err(PositionError::BadCastling(c));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Self {
+            board: [Piece::NULL; 64],
+            pieces: [Bitboard::ZERO; 6],
+            colors: [Bitboard::ZERO; 2],
+            ply: 0,
+            to_move: White,
+            state: State::default(),
+            castling_rooks: [[File::H, File::A]; 2],
+        }
+    }
+}
+
+impl FromStr for Position {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut schars = s.chars();
+        let mut p = Position::default();
+
+        let mut s = 56;
+        for c in schars.by_ref() {
+            if c == ' ' {
+                break;
+            }
+            if c.is_ascii_digit() {
+                debug_assert!(c != '0' && c != '9');
+                s += c as u8 - b'0';
+            } else if c == '/' {
+                s -= 16;
+            } else {
+                if let Ok(pc) = Piece::try_from(c) {
+                    let sq = unsafe { Square::new(s) };
+                    debug_assert!(sq.is_ok());
+                    p.add_piece(sq, pc);
+                } else {
+                    return Err("Invalid piece char found");
+                }
+                s += 1;
+            }
+        }
+        // Everything from the castling rights on looks at the kings
+        if [White, Black].iter().any(|&c| p.spec(PType::King, c).popcnt() != 1) {
+            return Err("Each side needs exactly one king");
+        }
+
+        let Some(c) = schars.next() else {
+            return Err("No color field given");
+        };
+
+        let Some(color) = Color::from_fen_char(c) else {
+            return Err("Invalid color given");
+        };
+        p.to_move = color;
+
+        if schars.next() != Some(' ') {
+            return Err("No field 3");
+        }
+
+        for c in schars.by_ref() {
+            if c == ' ' {
+                break;
+            } else if c == '-' {
+                debug_assert_eq!(p.state.castle.inner(), 0);
+                if schars.next() != Some(' ') {
+                    return Err("No field 4 given");
+                }
+                break;
+            }
+
+            let bit = match c {
+                'K' => p.outer_castle_bit(White, true),
+                'Q' => p.outer_castle_bit(White, false),
+                'k' => p.outer_castle_bit(Black, true),
+                'q' => p.outer_castle_bit(Black, false),
+                'A'..='H' | 'a'..='h' => p.shredder_castle_bit(c)?,
+                _ => return Err("Unknown castling character"),
+            };
+            if p.state.castle.inner() & bit != 0 {
+                return Err("Ambiguous castling rights");
+            }
+            p.state.castle.0 |= bit;
+        }
+
+        if let Some(c) = schars.next() {
+            if c == '-' {
+                p.state.ep = unsafe { Square::new(64) };
+            } else {
+                let Ok(f) = File::try_from(c) else {
+                    return Err("Invalid file given");
+                };
+                let Some(cn) = schars.next() else {
+                    return Err("No rank given for EP");
+                };
+                let Ok(r) = Rank::try_from(cn) else {
+                    return Err("Invalid rank given");
+                };
+                p.state.ep = Square::create(f, r);
+            }
+        } else {
+            return Err("No EP specifier");
+        }
+
+        // The clocks are often left off, so they default to "0 1"
+        let rest = schars.collect::<String>();
+        let mut clocks = rest.split_whitespace();
+        if let Some(halfmove) = clocks.next() {
+            let Ok(halfmove) = halfmove.parse::<u16>() else {
+                return Err("Invalid halfmove clock");
+            };
+            p.set_halfmove_clock(halfmove as i32);
+        }
+        let fullmove = match clocks.next() {
+            Some(fullmove) => fullmove.parse::<u32>().map_err(|_| "Invalid fullmove number")?,
+            None => 1,
+        };
+        p.set_fullmove(fullmove)?;
+
+        p.finish_setup();
+        Ok(p)
+    }
+}
+
+// The raw arrays are unreadable in test failures, so show the FEN instead.
+// `{:#?}` also prints the board.
+impl fmt::Debug for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Position")
+            .field("fen", &self.fen())
+            .field("to_move", &self.to_move)
+            .field("castle", &self.state.castle)
+            .finish()?;
+        if f.alternate() {
+            write!(f, "\n{self}")?;
+        }
+        Ok(())
+    }
+}
+
+// Extra markings for the board diagram
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct DisplayOpts {
+    // Its from and to squares are bracketed, e.g. [N]
+    pub last_move: Option<Move>,
+    // The side to move's king is starred when in check, e.g. *K*
+    pub mark_check: bool,
+}
+
+impl Position {
+    pub fn display_with(&self, opts: DisplayOpts) -> String {
+        let mut s = String::with_capacity(200);
+        let sep = " +---+---+---+---+---+---+---+---+\n";
+        let checked = if opts.mark_check && self.in_check() {
+            self.king(self.to_move)
+        } else {
+            Square::NULL
+        };
+
+        for i in 0..8 {
+            s.push_str(sep);
+            for j in 0..8 {
+                s.push_str(if j == 0 { " |" } else { "|" });
+                let k = (8 * (7 - i)) + j;
+                let sq = unsafe { Square::new(k) };
+                let p = self.piece_on(sq);
+
+                let (l, r) = if sq == checked {
+                    ('*', '*')
+                } else if opts.last_move.is_some_and(|m| m.from() == sq || m.to() == sq) {
+                    ('[', ']')
+                } else {
+                    (' ', ' ')
+                };
+                s.push(l);
+                if p.is_ok() {
+                    s.push_str(&p.to_string());
+                } else {
+                    s.push(' ');
+                }
+                s.push(r);
+            }
+            s.push_str("| ");
+            s.push((b'8' - i) as char);
+            s.push('\n');
+        }
+        s.push_str(sep);
+        s.push_str("   a   b   c   d   e   f   g   h\n");
+
+        s
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.display_with(DisplayOpts::default()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DisplayOpts;
+    use super::GameResult;
+    use super::MoveInfo;
+    use super::Position as Pos;
+    use crate::prelude::{generate_legal, individual_squares::*, king_attack, AccumulatorListener, Bitboard, Color, MType, Move, MoveList, PType, Piece, Square};
+    use crate::prng::Prng;
+    use std::str::FromStr;
+
+    const STARTPOS_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+    const KIWI_FEN: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq -";
+    const P3_FEN: &str = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - -";
+
+    fn setup() {
+        crate::init_for_tests();
+    }
+
+    #[test]
+    fn move_roundtrip_fuzz() {
+        setup();
+        let mut rng = Prng::new(0x5eed_c0ffee);
+        let mut moves = MoveList::new();
+
+        for fen in [STARTPOS_FEN, KIWI_FEN, P3_FEN] {
+            for _ in 0..50 {
+                let mut p = Pos::from_str(fen).unwrap();
+                for _ in 0..40 {
+                    generate_legal::<true>(&p, &mut moves);
+                    if moves.is_empty() {
+                        break;
+                    }
+                    for i in 0..moves.len() {
+                        p.assert_move_roundtrip(moves.get(i));
+                    }
+                    let m = moves.get(rng.sample() as usize % moves.len());
+                    p.do_move(m);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn shredder_castling() {
+        setup();
+        let p = Pos::from_str("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w HAha - 0 1").unwrap();
+        assert_eq!(p.fen(), Pos::startpos().fen());
+
+        let mut p = Pos::from_str("r3k2r/8/8/8/8/8/8/R3K2R w HAha - 0 1").unwrap();
+        let mut moves = MoveList::new();
+        generate_legal::<true>(&p, &mut moves);
+        let castles = (0..moves.len())
+            .map(|i| moves.get(i))
+            .filter(|m| m.kind() == MType::Castle)
+            .collect::<Vec<_>>();
+        assert_eq!(castles.len(), 2);
+        for m in castles {
+            assert!(p.is_legal(m));
+            p.assert_move_roundtrip(m);
+        }
+
+        assert!(Pos::from_str("r3k2r/8/8/8/8/8/8/R3K2R w KH - 0 1").is_err());
+        assert!(Pos::from_str("r3k2r/8/8/8/8/8/8/R3K2R w G - 0 1").is_err());
+
+        // The file of a rook that isn't the outermost one is written out
+        let p = Pos::from_str("4k3/8/8/8/8/8/8/RR2K3 w B - 0 1").unwrap();
+        assert_eq!(p.castling_rook(Color::White, false), B1);
+        assert_eq!(p.fen(), "4k3/8/8/8/8/8/8/RR2K3 w B - 0 1");
+    }
+
+    #[test]
+    fn chess960_castling() {
+        setup();
+        // Double Fischer random: each side has its own start
+        let p = Pos::from_str("nrbkqbrn/pppppppp/8/8/8/8/PPPPPPPP/BQNBRKRN w GEgb - 0 1").unwrap();
+        assert_eq!(p.castling_rook(Color::White, true), G1);
+        assert_eq!(p.castling_rook(Color::White, false), E1);
+        assert_eq!(p.castling_rook(Color::Black, true), G8);
+        assert_eq!(p.castling_rook(Color::Black, false), B8);
+        assert_eq!(p.fen(), "nrbkqbrn/pppppppp/8/8/8/8/PPPPPPPP/BQNBRKRN w KQkq - 0 1");
+        assert_eq!(Pos::from_str(&p.fen()).unwrap().castling_rook(Color::Black, false), B8);
+        assert_eq!(p.validate(), Ok(()));
+
+        let castles = |fen: &str| {
+            let mut p = Pos::from_str(fen).unwrap();
+            let moves = p.legal_moves().into_iter().filter(|m| m.kind() == MType::Castle);
+            let mut after = Vec::new();
+            for m in moves.collect::<Vec<_>>() {
+                p.assert_move_roundtrip(m);
+                let mut q = p.clone();
+                q.do_move(m);
+                after.push(q.fen());
+            }
+            after
+        };
+
+        // The king crosses to either side, or stays put while the rook comes
+        // over, or swaps squares with it
+        assert_eq!(
+            castles("4k3/8/8/8/8/8/8/RK5R w HA - 0 1"),
+            ["4k3/8/8/8/8/8/8/R4RK1 b - - 1 1", "4k3/8/8/8/8/8/8/2KR3R b - - 1 1"]
+        );
+        assert_eq!(
+            castles("4k3/8/8/8/8/8/8/R5KR w HA - 0 1"),
+            ["4k3/8/8/8/8/8/8/R4RK1 b - - 1 1", "4k3/8/8/8/8/8/8/2KR3R b - - 1 1"]
+        );
+        assert_eq!(castles("4k3/8/8/8/8/8/8/5KR1 w G - 0 1"), ["4k3/8/8/8/8/8/8/5RK1 b - - 1 1"]);
+        assert_eq!(castles("1r2k3/8/8/8/8/8/8/4K3 b b - 0 1"), ["2kr4/8/8/8/8/8/8/4K3 w - - 1 2"]);
+
+        // The castling rook shields the king from the queen, and f1 is attacked
+        let p = Pos::from_str("4k3/8/8/8/8/8/8/qRK5 w B - 0 1").unwrap();
+        assert!(p.is_pseudo_legal(Move::castle(C1, B1)));
+        assert!(!p.is_legal(Move::castle(C1, B1)));
+        assert!(castles("4k3/8/8/8/8/8/5r2/1K5R w H - 0 1").is_empty());
+
+        // Moving the king or a castling rook loses the right, wherever it stood
+        let mut p = Pos::from_str("1r2k1r1/8/8/8/8/8/8/1R2K1R1 w GBgb - 0 1").unwrap();
+        p.apply_uci_moves("g1g2 b8b7").unwrap();
+        assert_eq!(p.fen(), "4k1r1/1r6/8/8/8/8/6R1/1R2K3 w Qk - 2 2");
+
+        // UCI castling reads as e1g1 or as king takes rook, and prints the same way
+        let p = Pos::from_str("4k3/8/8/8/8/8/8/1R2K2R w HB - 0 1").unwrap();
+        let oo = p.parse_uci_move("e1g1").unwrap();
+        assert_eq!(oo, Move::castle(E1, H1));
+        assert_eq!(p.parse_uci_move("e1h1"), Ok(oo));
+        assert_eq!(p.parse_uci_move("e1c1"), Ok(Move::castle(E1, B1)));
+        assert_eq!(oo.to_string(), "e1g1");
+        assert_eq!(Move::castle(E1, B1).to_string(), "e1b1");
+        assert_eq!(p.san(Move::castle(E1, B1)), "O-O-O");
+
+        // A published Chess960 perft position
+        let mut p =
+            Pos::from_str("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9")
+                .unwrap();
+        assert_eq!(p.perft::<false>(1), 21);
+        assert_eq!(p.perft::<false>(2), 528);
+        assert_eq!(p.perft::<false>(3), 12189);
+        assert_eq!(p.perft::<false>(4), 326672);
+    }
+
+    #[test]
+    fn check_can_be_blocked_or_captured() {
+        setup();
+        let can_block = |fen| Pos::from_str(fen).unwrap().can_block_check();
+        // Re2 steps in, b2-b4 pushes in, Nxd3 takes the knight
+        assert!(can_block("k3r3/8/8/8/8/8/3R4/4K3 w - - 0 1"));
+        assert!(can_block("4k3/8/8/b7/8/8/1P6/4K3 w - - 0 1"));
+        assert!(can_block("4k3/8/8/8/1N6/3n4/8/4K3 w - - 0 1"));
+        // exd6 e.p. removes the checking pawn
+        assert!(can_block("8/8/8/3pP3/4K3/8/8/k7 w - d6 0 1"));
+
+        // Nothing reaches the file, and the bishop is pinned
+        assert!(!can_block("4r1k1/8/8/8/8/8/8/R3K3 w - - 0 1"));
+        assert!(!can_block("4r1k1/8/8/8/7q/8/5B2/4K3 w - - 0 1"));
+        assert!(!can_block("4k3/8/8/8/8/3n4/8/R3K2R w KQ - 0 1"));
+
+        // Evasions then skip straight to the king
+        for fen in ["4r1k1/8/8/8/8/8/8/R3K3 w - - 0 1", "4r1k1/8/8/8/7q/8/5B2/4K3 w - - 0 1"] {
+            let p = Pos::from_str(fen).unwrap();
+            assert!(p.legal_moves().into_iter().all(|m| m.from() == E1), "{fen}");
+        }
+    }
+
+    #[test]
+    fn knight_check_has_no_interpositions() {
+        setup();
+        let p = Pos::from_str("4k3/8/8/8/8/3n4/8/R3K2R w KQ - 0 1").unwrap();
+        assert!(!p.can_block_check());
+
+        let mut moves = MoveList::new();
+        generate_legal::<true>(&p, &mut moves);
+        let knight = p.checking_pieces().get_square();
+        for i in 0..moves.len() {
+            let m = moves.get(i);
+            assert!(m.from() == p.king(p.to_move()) || m.to() == knight, "{m}");
+        }
+    }
+
+    // The straightforward version attacks_to_occ is checked against
+    fn reference_attacks_to(p: &Pos, square: crate::prelude::Square, occ: Bitboard) -> Bitboard {
+        use crate::prelude::*;
+        let pawns = (pawn_attack(square, Color::White) & p.spec(PType::Pawn, Color::Black))
+            | (pawn_attack(square, Color::Black) & p.spec(PType::Pawn, Color::White));
+        let knights = knight_attack(square) & p.piece(PType::Knight);
+        let kings = king_attack(square) & p.piece(PType::King);
+        let bish = bishop_moves(square, occ) & p.piece_2t(PType::Bishop, PType::Queen);
+        let rook = rook_moves(square, occ) & p.piece_2t(PType::Rook, PType::Queen);
+        pawns | knights | kings | bish | rook
+    }
+
+    #[test]
+    fn attacks_to_occ_fuzz() {
+        setup();
+        let mut rng = Prng::new(0xa77ac);
+        let mut moves = MoveList::new();
+
+        for fen in [STARTPOS_FEN, KIWI_FEN, P3_FEN] {
+            let mut p = Pos::from_str(fen).unwrap();
+            for _ in 0..60 {
+                // Random occupancies include x-ray views through removed pieces
+                let occ = p.all() & Bitboard::new(rng.sample() | rng.sample());
+                for s in Bitboard::MAX {
+                    assert_eq!(p.attacks_to_occ(s, occ), reference_attacks_to(&p, s, occ));
+                    assert_eq!(p.attacks_to_occ(s, p.all()), reference_attacks_to(&p, s, p.all()));
+                    for c in [Color::White, Color::Black] {
+                        let by = reference_attacks_to(&p, s, occ) & p.color(c);
+                        assert_eq!(p.attackers_by(s, occ, c), by);
+                        assert_eq!(p.is_attacked_by(s, occ, c), by.nonzero());
+                    }
+                }
+
+                generate_legal::<true>(&p, &mut moves);
+                if moves.is_empty() {
+                    break;
+                }
+                p.do_move(moves.get(rng.sample() as usize % moves.len()));
+            }
+        }
+    }
+
+    #[test]
+    fn attackers_with_their_pieces() {
+        setup();
+        let p = Pos::from_str("4k3/8/8/8/4r3/2n5/8/R3K3 w - - 0 1").unwrap();
+        assert_eq!(
+            p.attacker_list(A4),
+            vec![
+                (A1, Piece::new(PType::Rook, Color::White)),
+                (C3, Piece::new(PType::Knight, Color::Black)),
+                (E4, Piece::new(PType::Rook, Color::Black)),
+            ]
+        );
+        assert!(p.attacker_list(H5).is_empty());
+    }
+
+    #[test]
+    fn attacks_of_single_pieces() {
+        setup();
+        let p = Pos::from_str("4k3/8/1r6/8/1R1Q4/8/1P6/4K3 w - - 0 1").unwrap();
+
+        let queen = [C5, B6, E5, F6, G7, H8, C3, B2, E3, F2, G1, D5, D6, D7, D8, D3, D2, D1, C4, B4,
+                     E4, F4, G4, H4];
+        assert_eq!(p.piece_attacks(D4), queen.iter().fold(Bitboard::ZERO, |b, &s| b | s));
+
+        // Stops at the first piece each way, friend or foe
+        let rook = [B5, B6, B3, B2, A4, C4, D4];
+        assert_eq!(p.piece_attacks(B4), rook.iter().fold(Bitboard::ZERO, |b, &s| b | s));
+
+        assert_eq!(p.piece_attacks(B2), Bitboard::from(A3) | C3);
+        assert_eq!(p.piece_attacks(E8), king_attack(E8));
+        assert_eq!(p.piece_attacks(H5), Bitboard::ZERO);
+    }
+
+    #[test]
+    fn attackers_by_matches_attacks_to() {
+        setup();
+        let p = Pos::kiwipete();
+        for s in Bitboard::MAX {
+            for c in [Color::White, Color::Black] {
+                assert_eq!(p.attackers_by(s, p.all(), c), p.attacks_to(s) & p.color(c));
+            }
+        }
+        assert_eq!(p.king_attackers(), p.state().checkers());
+    }
+
+    #[derive(Default)]
+    struct Recorder(Vec<(bool, crate::prelude::Square, Piece)>);
+
+    impl AccumulatorListener for Recorder {
+        fn piece_added(&mut self, square: crate::prelude::Square, piece: Piece) {
+            self.0.push((true, square, piece));
+        }
+        fn piece_removed(&mut self, square: crate::prelude::Square, piece: Piece) {
+            self.0.push((false, square, piece));
+        }
+    }
+
+    #[test]
+    fn accumulator_events_for_capture() {
+        setup();
+        let mut p = Pos::kiwipete();
+        let knight = Piece::new(PType::Knight, Color::White);
+        let pawn = Piece::new(PType::Pawn, Color::Black);
+        let m = Move::new(E5, F7);
+
+        let mut rec = Recorder::default();
+        p.do_move_with(m, &mut rec);
+        assert_eq!(rec.0, [(false, F7, pawn), (false, E5, knight), (true, F7, knight)]);
+
+        rec.0.clear();
+        p.undo_move_with(m, &mut rec);
+        assert_eq!(rec.0, [(false, F7, knight), (true, E5, knight), (true, F7, pawn)]);
+        assert_eq!(p.fen(), Pos::kiwipete().fen());
+    }
+
+    #[test]
+    fn bitboard_snapshot() {
+        setup();
+        let p = Pos::startpos();
+        let (pieces, colors) = p.bitboards();
+
+        let all = pieces.iter().fold(Bitboard::ZERO, |acc, &b| acc | b);
+        assert_eq!(all, p.all());
+        assert_eq!(colors[0] | colors[1], p.all());
+        assert_eq!(colors[Color::White as usize], p.color(Color::White));
+
+        let counts: Vec<u32> = pieces.iter().map(|b| b.popcnt()).collect();
+        assert_eq!(counts, [16, 4, 4, 4, 2, 2]);
+    }
+
+    #[test]
+    fn gives_check_fuzz() {
+        setup();
+        let mut rng = Prng::new(0xc4ec);
+        let mut moves = MoveList::new();
+
+        for fen in [STARTPOS_FEN, KIWI_FEN, P3_FEN] {
+            let mut p = Pos::from_str(fen).unwrap();
+            for _ in 0..80 {
+                generate_legal::<true>(&p, &mut moves);
+                if moves.is_empty() {
+                    break;
+                }
+                // Check info is filled in lazily, so it must survive undo_move
+                for i in 0..moves.len() {
+                    let m = moves.get(i);
+                    let check = p.gives_check(m);
+                    p.do_move(m);
+                    assert_eq!(check, p.in_check(), "{m} in {}", p.fen());
+                    p.undo_move(m);
+                }
+                p.do_move(moves.get(rng.sample() as usize % moves.len()));
+            }
+        }
+    }
+
+    // Every move that could be encoded, legal or not
+    fn every_move() -> Vec<Move> {
+        let mut all = Vec::new();
+        for from in (0..64).map(|i| unsafe { Square::new(i) }) {
+            for to in (0..64).map(|i| unsafe { Square::new(i) }) {
+                all.push(Move::new(from, to));
+                all.push(Move::en_passant(from, to));
+                all.push(Move::castle(from, to));
+                for pt in [PType::Knight, PType::Bishop, PType::Rook, PType::Queen] {
+                    all.push(Move::promotion(from, to, pt));
+                }
+            }
+        }
+        all
+    }
+
+    #[test]
+    fn pseudo_legal_agrees_with_movegen() {
+        setup();
+        let all = every_move();
+        let mut rng = Prng::new(0x9e1f);
+        for fen in [
+            KIWI_FEN,
+            P3_FEN,
+            PROMO_FEN,
+            "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1",
+            // In check, by a slider and by a pawn that ep can take
+            "4k3/8/8/8/1b6/8/8/R3K2R w KQ - 0 1",
+            "8/8/8/2k5/3Pp3/8/8/4K3 b - d3 0 1",
+            // Chess960, with a king that castles without moving
+            "1r4kr/8/8/8/8/8/8/RK5R w HAhb - 0 1",
+        ] {
+            let mut p = Pos::from_str(fen).unwrap();
+            for _ in 0..12 {
+                let legal = p.legal_moves();
+                let accepted = all
+                    .iter()
+                    .filter(|&&m| p.is_pseudo_legal(m) && p.is_legal(m))
+                    .count();
+                assert_eq!(accepted, legal.len(), "{}", p.fen());
+                assert!(legal.as_slice().iter().all(|&m| p.is_pseudo_legal(m)));
+                if legal.is_empty() {
+                    break;
+                }
+                p.do_move(legal.get(rng.sample() as usize % legal.len()));
+            }
+        }
+    }
+
+    #[test]
+    fn pseudo_legal_rejects_garbage() {
+        setup();
+        let p = Pos::kiwipete();
+        // Nothing on d4, a black knight, a queen blocked by the f6 knight
+        assert!(!p.is_pseudo_legal(Move::new(D4, D5)));
+        assert!(!p.is_pseudo_legal(Move::new(B6, C4)));
+        assert!(!p.is_pseudo_legal(Move::new(F3, F7)));
+        assert!(p.is_pseudo_legal(Move::new(F3, F6)));
+        // No ep square, and a push that should have been a promotion
+        assert!(!p.is_pseudo_legal(Move::en_passant(D5, C6)));
+        assert!(!p.is_pseudo_legal(Move::NULL));
+        assert!(!p.is_pseudo_legal(Move::null_move()));
+
+        let p = Pos::from_str("4k3/1P6/8/8/8/8/8/R3K1NR w KQ - 0 1").unwrap();
+        assert!(!p.is_pseudo_legal(Move::new(B7, B8)));
+        assert!(p.is_pseudo_legal(Move::promotion(B7, B8, PType::Queen)));
+        // The knight blocks O-O, O-O-O is fine
+        assert!(!p.is_pseudo_legal(Move::castle(E1, H1)));
+        assert!(p.is_pseudo_legal(Move::castle(E1, A1)));
+        assert!(!p.is_pseudo_legal(Move::castle(E1, C1)));
+        assert!(!p.is_pseudo_legal(Move::new(E1, C1)));
+        assert!(!p.is_pseudo_legal(Move::new(E1, A1)));
+        assert!(p.is_pseudo_legal(Move::new(A1, A8)));
+        assert!(!p.is_pseudo_legal(Move::new(E8, D8)));
+    }
+
+    #[test]
+    fn gives_check_matches_playing_the_move() {
+        setup();
+        fn walk(p: &mut Pos, depth: usize) {
+            for m in p.legal_moves() {
+                let check = p.gives_check(m);
+                p.do_move(m);
+                assert_eq!(check, p.in_check(), "{m} in {}", p.fen());
+                if depth > 1 {
+                    walk(p, depth - 1);
+                }
+                p.undo_move(m);
+            }
+        }
+
+        for fen in [
+            // O-O-O checks along the rank the king just left
+            "8/8/8/8/8/8/8/R3K2k w Q - 0 1",
+            "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1",
+            // Discovered checks by en passant and by promotion
+            "8/8/8/R2pP2k/8/8/8/4K3 w - d6 0 1",
+            "8/8/8/8/K2pP2r/8/8/7k b - e3 0 1",
+            PROMO_FEN,
+            KIWI_FEN,
+            // Chess960 rooks checking from f1 and d1
+            "5k2/8/8/8/8/8/8/R5KR w HA - 0 1",
+            "3k4/8/8/8/8/8/8/RK5R w HA - 0 1",
+        ] {
+            walk(&mut Pos::from_str(fen).unwrap(), 2);
+        }
+    }
+
+    #[test]
+    fn shared_across_threads() {
+        setup();
+        let p = Pos::from_str(KIWI_FEN).unwrap();
+        let checks = |p: &Pos| p.legal_moves().into_iter().filter(|&m| p.gives_check(m)).count();
+        let here = checks(&p);
+        std::thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| assert_eq!(checks(&p), here));
+            }
+        });
+    }
+
+    #[test]
+    fn checkmate_status() {
+        setup();
+        let mut moves = MoveList::new();
+        let p = Pos::from_str("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1").unwrap();
+        assert_eq!(p.status_with_moves(&mut moves), GameResult::Checkmate);
+        assert!(moves.is_empty());
+
+        let p = Pos::from_str("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+        assert_eq!(p.status_with_moves(&mut moves), GameResult::Stalemate);
+
+        let p = Pos::startpos();
+        assert_eq!(p.status_with_moves(&mut moves), GameResult::Ongoing);
+        assert_eq!(moves.len(), 20);
+    }
+
+    #[test]
+    fn full_key_sees_halfmove_clock() {
+        setup();
+        let a = Pos::from_str("4k3/8/8/8/8/8/8/R3K3 w - - 0 1").unwrap();
+        let mut b = a.clone();
+        b.set_halfmove_clock(97);
+        assert_eq!(a.transposition_key(), b.transposition_key());
+        assert_ne!(a.full_key(), b.full_key());
+
+        // Reached by moves instead, the clock ends up the same
+        let mut c = a.clone();
+        c.set_halfmove_clock(95);
+        c.apply_uci_moves("a1a2 e8d8").unwrap();
+        c.apply_uci_moves("a2a1 d8e8").unwrap();
+        assert_eq!(c.transposition_key(), a.transposition_key());
+        b.set_halfmove_clock(99);
+        assert_eq!(c.full_key(), b.full_key());
+    }
+
+    #[test]
+    fn total_material_both_sides() {
+        setup();
+        let (w, b) = Pos::startpos().total_material();
+        assert_eq!(w, b);
+        assert_eq!(w + b, 2 * (8 * 100 + 2 * 300 + 2 * 300 + 2 * 500 + 900));
+
+        let p = Pos::from_str("4k3/8/8/8/8/8/8/R3K2Q w - - 0 1").unwrap();
+        assert_eq!(p.total_material(), (1400, 0));
+        assert_eq!(p.total_material().0, p.material(Color::White));
+    }
+
+    #[test]
+    fn fen_followed_by_moves() {
+        setup();
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let mut expected = Pos::startpos();
+        expected.apply_uci_moves("e2e4 c7c5").unwrap();
+
+        for s in [
+            format!("{start} e2e4 c7c5"),
+            format!("{start} moves e2e4 c7c5"),
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - e2e4 c7c5".to_string(),
+        ] {
+            let p = Pos::from_fen_and_moves(&s).unwrap();
+            assert_eq!(p.fen(), expected.fen(), "{s}");
+            assert_eq!(p.state().key(), expected.state().key());
+        }
+
+        assert!(Pos::from_fen_and_moves(start).is_ok());
+        assert!(Pos::from_fen_and_moves(&format!("{start} e2e5")).is_err());
+    }
+
+    #[test]
+    fn epd_roundtrip() {
+        setup();
+        let p = Pos::kiwipete();
+        let epd = p.to_epd(&[("id", "test"), ("bm", "Qxf6")]);
+        assert_eq!(
+            epd,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - id \"test\"; bm Qxf6;"
+        );
+
+        let (q, ops) = Pos::from_epd(&epd).unwrap();
+        assert_eq!(q.fen(), p.fen());
+        assert_eq!(
+            ops,
+            [("id".to_string(), "test".to_string()), ("bm".to_string(), "Qxf6".to_string())]
+        );
+        assert_eq!(Pos::startpos().to_epd(&[]).split(' ').count(), 4);
+
+        let epd = format!("{KIWI_FEN}   id  \"a; b\" ;bm Qxf6   Qd1;c0 \"x\"");
+        let (q, ops) = Pos::from_epd(&epd).unwrap();
+        assert_eq!(q.fen(), p.fen());
+        let ops = ops.iter().map(|(c, o)| (c.as_str(), o.as_str())).collect::<Vec<_>>();
+        assert_eq!(ops, [("id", "a; b"), ("bm", "Qxf6 Qd1"), ("c0", "x")]);
+        assert!(Pos::from_epd(&format!("{KIWI_FEN} id \"open;")).is_err());
+        assert!(Pos::from_epd("8/8/8/8 w").is_err());
     }
 
-    #[inline]
-    pub const fn pawn_key(&self) -> Key {
-        self.pawn_key
+    #[test]
+    fn in_check_for_either_side() {
+        setup();
+        let p = Pos::from_str("4k3/8/8/1B6/8/8/8/4K3 b - - 0 1").unwrap();
+        assert!(p.is_in_check(Color::Black));
+        assert!(!p.is_in_check(Color::White));
+
+        let p = Pos::startpos();
+        assert!(!p.is_in_check(Color::White) && !p.is_in_check(Color::Black));
     }
-}
 
-impl Castle {
-    #[inline]
-    const fn inner(self) -> u8 {
-        self.0
+    #[test]
+    fn do_move_reports_capture_and_check() {
+        setup();
+        let mut p = Pos::from_str("4k3/8/8/4p3/3pP3/8/8/R3K3 b - e3 0 1").unwrap();
+        let info = p.do_move(Move::en_passant(D4, E3));
+        assert_eq!(info.captured, Piece::new(PType::Pawn, Color::White));
+        assert!(!info.gives_check);
+
+        let info = p.do_move(Move::new(A1, A8));
+        assert_eq!(info, MoveInfo { captured: Piece::NULL, gives_check: true });
+        p.undo_move(Move::new(A1, A8));
+
+        p.do_move(Move::new(E1, D1));
+        let info = p.do_move(Move::new(E3, E2));
+        assert!(info.gives_check);
+        let info = p.do_move(Move::new(D1, E2));
+        assert_eq!(info.captured, Piece::new(PType::Pawn, Color::Black));
+        assert!(!info.gives_check);
+
+        // Qxd8+ does both at once
+        let mut p = Pos::from_str("3rk3/8/8/8/8/8/8/3QK3 w - - 0 1").unwrap();
+        let info = p.do_move(Move::new(D1, D8));
+        assert_eq!(
+            info,
+            MoveInfo { captured: Piece::new(PType::Rook, Color::Black), gives_check: true }
+        );
+        assert!(p.in_check());
     }
 
-    #[inline]
-    pub const fn castle_for(self, color: Color) -> (bool, bool) {
-        let king = match color {
-            White => 1,
-            Black => 4,
-        };
-        let queen = match color {
-            White => 2,
-            Black => 8,
+    #[test]
+    fn validate_each_invariant() {
+        use super::PositionError::*;
+        setup();
+        let ok = |fen: &str| Pos::from_str(fen).unwrap().validate();
+
+        assert_eq!(Pos::startpos().validate(), Ok(()));
+        assert_eq!(Pos::kiwipete().validate(), Ok(()));
+        assert_eq!(ok("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1"), Ok(()));
+
+        let mut p = Pos::startpos();
+        p.set_piece(E8, Piece::NULL);
+        assert_eq!(p.validate(), Err(KingCount(Color::Black)));
+        p.set_piece(E8, Piece::new(PType::King, Color::Black));
+        p.set_piece(E4, Piece::new(PType::King, Color::White));
+        assert_eq!(p.validate(), Err(KingCount(Color::White)));
+
+        assert_eq!(ok("4k3/8/8/8/8/8/8/4K2R w - - 0 1"), Ok(()));
+        assert_eq!(ok("4k3/8/8/8/8/8/8/4R1K1 w - - 0 1"), Err(OpponentInCheck));
+        assert_eq!(ok("4k2P/8/8/8/8/8/8/4K3 b - - 0 1"), Err(PawnOnBackRank(H8)));
+        assert_eq!(ok("4k3/8/8/8/8/8/8/p3K3 w - - 0 1"), Err(PawnOnBackRank(A1)));
+
+        // Nothing was pushed to d5, or it came from a square still occupied.
+        // Parsing would drop the first ep square, so set them afterwards
+        let bad_ep = |fen: &str| {
+            let mut p = Pos::from_str(fen).unwrap();
+            p.set_en_passant(Some(D6)).unwrap();
+            p.validate()
         };
-        let c = self.inner();
-        (c & king > 0, c & queen > 0)
+        assert_eq!(bad_ep("4k3/8/8/4P3/8/8/8/4K3 w - - 0 1"), Err(BadEnPassant(D6)));
+        assert_eq!(bad_ep("4k3/3p4/8/3pP3/8/8/8/4K3 w - - 0 1"), Err(BadEnPassant(D6)));
+
+        assert_eq!(ok("r3k3/8/8/8/8/8/8/4K2R w Kq - 0 1"), Ok(()));
+        assert_eq!(ok("4k3/8/8/8/8/8/8/4K2R w Q - 0 1"), Err(BadCastling(Color::White)));
+        assert_eq!(ok("4k2r/8/8/8/8/8/8/4K3 w q - 0 1"), Err(BadCastling(Color::Black)));
+        // Chess960: any king file, as long as the rook is on the named side
+        assert_eq!(ok("r4k2/8/8/8/8/8/8/4K3 w q - 0 1"), Ok(()));
+        assert_eq!(ok("1r3k2/8/8/8/8/8/8/4K3 w b - 0 1"), Ok(()));
     }
-}
 
-impl Default for Position {
-    fn default() -> Self {
-        Self {
-            board: [Piece::NULL; 64],
-            pieces: [Bitboard::ZERO; 6],
-            colors: [Bitboard::ZERO; 2],
-            ply: 0,
-            to_move: White,
-            state: State::default(),
+    #[test]
+    fn parsed_king_count() {
+        setup();
+        for fen in [
+            "4k3/8/8/8/8/8/8/8 w - - 0 1",
+            "4k3/8/8/8/8/8/8/8 b - - 0 1",
+            "8/8/8/8/8/8/8/4K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/3KK3 w - - 0 1",
+            "8/8/8/8/8/8/8/8 w - - 0 1",
+        ] {
+            assert_eq!(Pos::from_str(fen).err(), Some("Each side needs exactly one king"), "{fen}");
         }
     }
-}
 
-impl FromStr for Position {
-    type Err = &'static str;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut schars = s.chars();
-        let mut p = Position::default();
+    #[test]
+    fn display_marks_move_and_check() {
+        setup();
+        let mut p = Pos::startpos();
+        let plain = p.to_string();
+        assert!(plain.contains(" | r | n | b | q | k | b | n | r | 8\n"));
+        assert_eq!(p.display_with(DisplayOpts::default()), plain);
+
+        p.do_move(Move::new(E2, E4));
+        let opts = DisplayOpts { last_move: Some(Move::new(E2, E4)), mark_check: true };
+        let board = p.display_with(opts);
+        assert!(board.contains(" |   |   |   |   |[P]|   |   |   | 4\n"), "{board}");
+        assert!(board.contains(" | P | P | P | P |[ ]| P | P | P | 2\n"), "{board}");
+        assert!(!board.contains('*'));
+
+        let p = Pos::from_str("4k3/8/8/1B6/8/8/8/4K3 b - - 0 1").unwrap();
+        let board = p.display_with(opts);
+        assert!(board.contains(" |   |   |   |   |*k*|   |   |   | 8\n"), "{board}");
+        assert!(!p.display_with(DisplayOpts::default()).contains('*'));
+    }
 
-        let mut s = 56;
-        for c in schars.by_ref() {
-            if c == ' ' {
-                break;
-            }
-            if c.is_ascii_digit() {
-                debug_assert!(c != '0' && c != '9');
-                s += c as u8 - b'0';
-            } else if c == '/' {
-                s -= 16;
-            } else {
-                if let Ok(pc) = Piece::try_from(c) {
-                    let sq = unsafe { Square::new(s) };
-                    debug_assert!(sq.is_ok());
-                    p.add_piece(sq, pc);
-                } else {
-                    return Err("Invalid piece char found");
-                }
-                s += 1;
-            }
+    #[test]
+    fn flipped_swaps_sides() {
+        setup();
+        let p = Pos::from_str("r3k2r/8/8/3pP3/8/8/8/R3K2R w Kq d6 0 1").unwrap();
+        let f = p.flipped();
+        assert_eq!(f.fen(), "r3k2r/8/8/8/3Pp3/8/8/R3K2R b Qk d3 0 1");
+        assert_eq!(f.flipped().fen(), p.fen());
+        assert_eq!(Pos::startpos().flipped().state().key(), {
+            let mut s = Pos::startpos();
+            s.set_to_move(Color::Black);
+            s.recompute_state();
+            s.state().key()
+        });
+    }
+
+    #[test]
+    fn capture_values() {
+        setup();
+        let p = Pos::from_str("4k3/8/8/3pP3/8/8/8/4K2R w K d6 0 1").unwrap();
+        assert_eq!(p.capture_value(Move::en_passant(E5, D6)), 100);
+        assert_eq!(p.capture_value(Move::new(H1, H7)), 0);
+        assert_eq!(p.capture_value(Move::castle(E1, H1)), 0);
+        assert!(!p.is_capture(Move::castle(E1, H1)));
+
+        let p = Pos::kiwipete();
+        assert_eq!(p.capture_value(Move::new(E2, A6)), 300);
+        assert_eq!(p.capture_value(Move::new(F3, F6)), 300);
+    }
+
+    #[test]
+    fn threefold_and_fivefold() {
+        setup();
+        let mut p = Pos::startpos();
+        assert_eq!(p.repetition_count(), 1);
+        // Each knight shuffle brings the start position back once more
+        for n in 2..=5 {
+            p.apply_uci_moves("g1f3 g8f6 f3g1 f6g8").unwrap();
+            assert_eq!(p.repetition_count(), n);
+            assert_eq!(p.threefold_repetition(), n >= 3);
+            assert_eq!(p.fivefold_repetition(), n >= 5);
         }
 
-        let Some(c) = schars.next() else {
-            return Err("No color field given");
+        // A pawn move resets the window
+        p.apply_uci_moves("e2e4").unwrap();
+        assert_eq!(p.repetition_count(), 1);
+    }
+
+    #[test]
+    fn depth_0() {
+        setup();
+        let mut p = Pos::startpos();
+        assert_eq!(p.perft::<true>(0), 1);
+        assert_eq!(p.perft::<false>(0), 1);
+    }
+    #[test]
+    fn built_positions_match_fen() {
+        setup();
+        assert_eq!(Pos::startpos().fen(), Pos::from_str(STARTPOS_FEN).unwrap().fen());
+        let kiwi = Pos::from_str(KIWI_FEN).unwrap();
+        assert_eq!(Pos::kiwipete().fen(), kiwi.fen());
+        assert_eq!(Pos::kiwipete().state().key(), kiwi.state().key());
+    }
+    #[test]
+    fn long_algebraic() {
+        setup();
+        let p = Pos::startpos();
+        assert_eq!(p.to_lan(Move::new(G1, F3)), "Ng1-f3");
+        assert_eq!(p.to_lan(Move::new(E2, E4)), "e2-e4");
+
+        let p = Pos::from_str("r3k3/1P3p2/8/8/8/8/8/4KR2 w - - 0 1").unwrap();
+        assert_eq!(p.to_lan(Move::new(F1, F7)), "Rf1xf7");
+        assert_eq!(
+            p.to_lan(Move::promotion(B7, A8, PType::Queen)),
+            "b7xa8=Q+"
+        );
+        assert_eq!(p.to_lan(Move::new(E1, D2)), "Ke1-d2");
+    }
+    #[test]
+    fn untyped_moves_match_generated() {
+        setup();
+        let p = Pos::from_str("4k3/1P6/8/3pP3/8/8/8/4K2R w K d6 0 1").unwrap();
+        let moves = p.legal_moves();
+        let generated = |from, to| {
+            moves.as_slice().iter().copied().find(|m| m.from() == from && m.to() == to).unwrap()
         };
 
-        if c == 'w' {
-            p.to_move = White;
-        } else if c == 'b' {
-            p.to_move = Black;
-        } else {
-            return Err("Invalid color given");
-        }
+        let ep = generated(E5, D6);
+        assert_eq!(ep.kind(), MType::EnPassant);
+        assert_ne!(ep, Move::new(E5, D6));
+        assert!(p.moves_equivalent(Move::new(E5, D6), ep));
+        assert!(p.moves_equivalent(Move::new(E1, G1), Move::castle(E1, H1)));
+        assert!(p.moves_equivalent(Move::new(E1, H1), Move::castle(E1, H1)));
+
+        let knight = Move::promotion(B7, B8, PType::Knight);
+        assert!(p.moves_equivalent(knight, Move::promotion(B7, B8, PType::Knight)));
+        assert!(!p.moves_equivalent(knight, Move::promotion(B7, B8, PType::Queen)));
+        assert!(!p.moves_equivalent(Move::new(B7, B8), knight));
+        assert!(!p.moves_equivalent(Move::new(E5, E6), ep));
+
+        // The board decides the kind, not the tag
+        assert!(p.moves_equivalent(Move::castle(E1, F1), Move::new(E1, F1)));
+        assert!(p.moves_equivalent(Move::en_passant(E5, D6), Move::castle(E5, D6)));
+        assert!(p.moves_equivalent(Move::new(H1, H2), Move::en_passant(H1, H2)));
+        assert!(!p.moves_equivalent(Move::promotion(B7, B8, PType::Queen), Move::castle(B7, B8)));
+    }
 
-        if schars.next() != Some(' ') {
-            return Err("No field 3");
-        }
+    #[test]
+    fn standard_algebraic() {
+        setup();
+        let p = Pos::startpos();
+        assert_eq!(p.san(Move::new(G1, F3)), "Nf3");
+        assert_eq!(p.san(Move::new(E2, E4)), "e4");
+
+        // Both pawns can take on d5, the file tells them apart
+        let p = Pos::from_str("4k3/8/8/3p4/2P1P3/8/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(p.san(Move::new(E4, D5)), "exd5");
+        assert_eq!(p.san(Move::new(C4, D5)), "cxd5");
+        let p = Pos::from_str("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1").unwrap();
+        assert_eq!(p.san(Move::en_passant(E5, D6)), "exd6");
+
+        // A pawn capture that mates
+        let p = Pos::from_str("6bk/6np/5P2/8/8/8/8/4K1R1 w - - 0 1").unwrap();
+        assert_eq!(p.san(Move::new(F6, G7)), "fxg7#");
+        assert_eq!(p.san(Move::new(F6, F7)), "f7");
+
+        let p = Pos::from_str("r3k3/1P6/8/8/8/8/8/R3K2R w KQ - 0 1").unwrap();
+        assert_eq!(p.san(Move::promotion(B7, A8, PType::Queen)), "bxa8=Q+");
+        assert_eq!(p.san(Move::promotion(B7, B8, PType::Knight)), "b8=N");
+        assert_eq!(p.san(Move::castle(E1, H1)), "O-O");
+        assert_eq!(p.san(Move::new(A1, A8)), "Rxa8+");
+        assert_eq!(p.san(Move::new(H1, F1)), "Rf1");
+
+        let p = Pos::from_str("4k3/8/8/R7/8/8/8/R4RK1 w - - 0 1").unwrap();
+        assert_eq!(p.san(Move::new(A1, D1)), "Rad1");
+        assert_eq!(p.san(Move::new(F1, D1)), "Rfd1");
+        assert_eq!(p.san(Move::new(A1, A3)), "R1a3");
+        assert_eq!(p.san(Move::new(F1, F8)), "Rf8+");
+    }
 
-        for c in schars.by_ref() {
-            if c == ' ' {
-                break;
-            } else if c == '-' {
-                debug_assert_eq!(p.state.castle.inner(), 0);
-                if schars.next() != Some(' ') {
-                    return Err("No field 4 given");
-                }
-                break;
-            }
+    #[test]
+    fn debug_shows_fen() {
+        setup();
+        let p = Pos::startpos();
+        assert!(format!("{p:?}").contains(&p.fen()));
+        assert!(format!("{p:#?}").contains("a   b   c"));
+    }
+    #[test]
+    fn apply_italian_game() {
+        setup();
+        let mut p = Pos::startpos();
+        assert_eq!(p.apply_uci_moves("e2e4 e7e5 g1f3 b8c6 f1c4 f8c5"), Ok(()));
+        assert_eq!(p.fen(), "r1bqk1nr/pppp1ppp/2n5/2b1p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4");
+
+        assert_eq!(p.apply_uci_moves("e1g1 g8f6  d2d3"), Ok(()));
+        assert_eq!(p.apply_uci_moves("a7a6 d8d1"), Err((1, "Illegal move")));
+        assert_eq!(p.apply_uci_moves("h2h3 e9e4"), Err((1, "Invalid square")));
+        assert_eq!(p.apply_uci_moves("e2"), Err((0, "Malformed move")));
+    }
+    #[test]
+    fn setters_then_recompute() {
+        setup();
+        let mut p = Pos::startpos();
+        p.set_to_move(Color::Black);
+        p.recompute_state();
 
-            match c {
-                'K' => {
-                    debug_assert_eq!(p.state.castle.inner() & 1, 0);
-                    p.state.castle.0 |= 1;
-                }
-                'Q' => {
-                    debug_assert_eq!(p.state.castle.inner() & 2, 0);
-                    p.state.castle.0 |= 2;
-                }
-                'k' => {
-                    debug_assert_eq!(p.state.castle.inner() & 4, 0);
-                    p.state.castle.0 |= 4;
-                }
-                'q' => {
-                    debug_assert_eq!(p.state.castle.inner() & 8, 0);
-                    p.state.castle.0 |= 8;
-                }
-                _ => return Err("Unknown castling character"),
-            }
+        let mut moves = MoveList::new();
+        generate_legal::<true>(&p, &mut moves);
+        assert_eq!(moves.len(), 20);
+        for i in 0..moves.len() {
+            assert_eq!(p.piece_on(moves.get(i).from()).color(), Color::Black);
         }
+        let black_fen = STARTPOS_FEN.replace(" w ", " b ");
+        assert_eq!(p.state().key(), Pos::from_str(&black_fen).unwrap().state().key());
+
+        let mut p = Pos::default();
+        p.set_piece(E1, Piece::new(PType::King, Color::White));
+        p.set_piece(E8, Piece::new(PType::King, Color::Black));
+        p.set_piece(D5, Piece::new(PType::Pawn, Color::White));
+        p.set_piece(E5, Piece::new(PType::Pawn, Color::Black));
+        assert!(p.set_en_passant(Some(E3)).is_err());
+        assert!(p.set_en_passant(Some(E6)).is_ok());
+        p.set_halfmove_clock(0);
+        assert!(p.set_fullmove(30).is_ok());
+        p.recompute_state();
+        assert_eq!(p.ply(), 58);
+        assert_eq!(p.fen(), "4k3/8/8/3Pp3/8/8/8/4K3 w - e6 0 30");
+
+        generate_legal::<true>(&p, &mut moves);
+        assert!((0..moves.len()).any(|i| moves.get(i).kind() == MType::EnPassant));
+    }
+    fn ep_capture_is_generated(p: &Pos, m: Move) -> bool {
+        let mut moves = MoveList::new();
+        generate_legal::<true>(p, &mut moves);
+        (0..moves.len()).any(|i| moves.get(i) == m)
+    }
+    #[test]
+    fn pinned_pieces() {
+        setup();
+        // The e4 knight is pinned by the e8 rook, the g4 knight is free.
+        // The f2 bishop pins Black's c5 knight against a7 in turn.
+        let p = Pos::from_str("4r3/k7/8/2n5/4N1N1/8/5B2/4K3 w - - 0 1").unwrap();
+        assert!(p.is_pinned(E4));
+        assert_eq!(p.pinned_direction(E4), Some((0, 1)));
+        assert!(!p.is_pinned(G4));
+        assert_eq!(p.pinned_direction(G4), None);
+        assert!(!p.is_pinned(F2) && !p.is_pinned(E1) && !p.is_pinned(A1));
+
+        assert!(p.is_pinned(C5));
+        assert_eq!(p.pinned_direction(C5), Some((1, -1)));
+        assert!(!p.is_pinned(E8));
+    }
 
-        if let Some(c) = schars.next() {
-            if c == '-' {
-                p.state.ep = unsafe { Square::new(64) };
-            } else {
-                let Ok(f) = File::try_from(c) else {
-                    return Err("Invalid file given");
-                };
-                let Some(cn) = schars.next() else {
-                    return Err("No rank given for EP");
-                };
-                let Ok(r) = Rank::try_from(cn) else {
-                    return Err("Invalid rank given");
-                };
-                p.state.ep = Square::create(f, r);
-            }
-        } else {
-            return Err("No EP specifier");
+    #[test]
+    fn fen_round_trips_with_clocks() {
+        setup();
+        for fen in [
+            STARTPOS_FEN,
+            &format!("{KIWI_FEN} 0 1"),
+            &format!("{P3_FEN} 0 1"),
+            "r1bq1rk1/pp2bppp/2n1pn2/3p4/2PP4/2N1PN2/PP2BPPP/R2QKB1R w KQ - 0 8",
+            "r4rk1/1pp1qppp/p1np1n2/4p3/4P3/2NP1N2/PPP1QPPP/R4RK1 b - - 3 14",
+        ] {
+            assert_eq!(Pos::from_str(fen).unwrap().fen(), fen);
         }
 
-        p.set_state();
-        Ok(p)
+        let mut p = Pos::startpos();
+        p.apply_uci_moves("g1f3 g8f6 f3g1").unwrap();
+        assert_eq!(p.fen(), "rnbqkb1r/pppppppp/5n2/8/8/8/PPPPPPPP/RNBQKBNR b KQkq - 3 2");
     }
-}
-
-impl fmt::Display for Position {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut s = String::with_capacity(200);
-        let sep = " +---+---+---+---+---+---+---+---+\n";
-
-        for i in 0..8 {
-            s.push_str(sep);
-            for j in 0..8 {
-                s.push_str(" | ");
-                let k = (8 * (7 - i)) + j;
-                let sq = unsafe { Square::new(k) };
-                let p = self.piece_on(sq);
 
-                if p.is_ok() {
-                    s.push_str(&p.to_string());
-                } else {
-                    s.push(' ');
-                }
-            }
-            s.push_str(" | ");
-            s.push((b'8' - i) as char);
-            s.push('\n');
+    #[test]
+    fn fen_clocks_are_parsed() {
+        setup();
+        let p = Pos::from_str("4k3/8/8/8/8/8/8/4K2R w K - 37 52").unwrap();
+        assert_eq!(p.state().rule50(), 37);
+        assert_eq!(p.ply(), 102);
+        let p = Pos::from_str("4k3/8/8/8/8/8/8/4K2R b K - 5 3").unwrap();
+        assert_eq!(p.state().rule50(), 5);
+        assert_eq!(p.ply(), 5);
+
+        // Left off they default to "0 1"
+        let p = Pos::from_str("4k3/8/8/8/8/8/8/4K2R b K -").unwrap();
+        assert_eq!((p.state().rule50(), p.ply()), (0, 1));
+        let p = Pos::from_str("4k3/8/8/8/8/8/8/4K2R w K - 12").unwrap();
+        assert_eq!((p.state().rule50(), p.ply()), (12, 0));
+
+        for bad in ["- -1 1", "- x 1", "- 0 y", "- 0 1073741825", "- 0 4294967295"] {
+            let fen = format!("4k3/8/8/8/8/8/8/4K2R w K {bad}");
+            assert!(Pos::from_str(&fen).is_err(), "{fen}");
         }
-        s.push_str(sep);
-        s.push_str("   a   b   c   d   e   f   g   h\n");
-
-        write!(f, "{s}")
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::Position as Pos;
-    use std::str::FromStr;
+    #[test]
+    fn write_fen_matches_fen() {
+        setup();
+        let mut buf = String::new();
+        for p in [Pos::startpos(), Pos::kiwipete()] {
+            buf.clear();
+            p.write_fen(&mut buf).unwrap();
+            assert_eq!(buf, p.fen());
+        }
 
-    const STARTPOS_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
-    const KIWI_FEN: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq -";
-    const P3_FEN: &str = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - -";
+        // Appends, so a batch can share one buffer
+        let p = Pos::from_str("4k3/8/8/3Pp3/8/8/8/4K3 w - e6 0 1").unwrap();
+        p.write_fen(&mut buf).unwrap();
+        assert_eq!(buf, format!("{}{}", Pos::kiwipete().fen(), p.fen()));
+    }
 
-    fn setup() {
-        crate::init::init();
-        crate::magic::initalize_magics();
+    #[test]
+    fn spurious_ep_square_is_dropped() {
+        setup();
+        // Nothing can take on e3
+        let p = Pos::from_str("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1").unwrap();
+        assert_eq!(p.en_passant(), None);
+        assert_eq!(p.fen(), "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1");
+        let mut q = Pos::from_str(Pos::STARTPOS).unwrap();
+        q.apply_uci_moves("e2e4").unwrap();
+        assert_eq!(p.state().key(), q.state().key());
+
+        // No pawn that could have just moved two squares
+        let p = Pos::from_str("4k3/8/8/3P4/8/8/8/4K3 w - e6 0 1").unwrap();
+        assert_eq!(p.en_passant(), None);
+
+        let p = Pos::from_str("4k3/8/8/3Pp3/8/8/8/4K3 w - e6 0 1").unwrap();
+        assert_eq!(p.en_passant(), Some(E6));
     }
 
+    #[test]
+    fn en_passant_legality() {
+        setup();
+        let exd3 = Move::en_passant(E4, D3);
+
+        let p = Pos::from_str("4k3/8/8/8/3Pp3/8/8/4K3 b - d3 0 1").unwrap();
+        assert!(p.is_legal(exd3));
+        assert!(ep_capture_is_generated(&p, exd3));
+
+        // The e-pawn is pinned to its king along the file
+        let p = Pos::from_str("4k3/8/8/8/3Pp3/8/8/4RK2 b - d3 0 1").unwrap();
+        assert!(!p.is_legal(exd3));
+        assert!(!ep_capture_is_generated(&p, exd3));
+
+        // Both pawns leave the rank, exposing the king to the rook
+        let p = Pos::from_str("8/8/8/8/k2Pp2R/8/8/4K3 b - d3 0 1").unwrap();
+        assert!(!p.is_legal(exd3));
+        assert!(!ep_capture_is_generated(&p, exd3));
+
+        // Along a diagonal pin the capture stays on the line
+        let p = Pos::from_str("8/7k/8/8/3Pp3/8/8/1B2K3 b - d3 0 1").unwrap();
+        assert!(p.is_legal(exd3));
+        assert!(ep_capture_is_generated(&p, exd3));
+
+        // Capturing en passant removes the checking pawn
+        let p = Pos::from_str("8/8/8/4k3/3Pp3/8/8/4K3 b - d3 0 1").unwrap();
+        assert!(p.in_check());
+        assert!(p.is_legal(exd3));
+        assert!(ep_capture_is_generated(&p, exd3));
+
+        // White's turn, from the other side of the board
+        let p = Pos::from_str("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1").unwrap();
+        let exd6 = Move::en_passant(E5, D6);
+        assert!(p.is_legal(exd6));
+        assert!(ep_capture_is_generated(&p, exd6));
+    }
+    #[test]
+    fn perft_capped() {
+        setup();
+        let mut p = Pos::kiwipete();
+        assert_eq!(p.perft_capped(3, 1000), (1000, true));
+        assert_eq!(p.perft_capped(3, 1_000_000), (97862, false));
+        assert_eq!(p.perft_capped(3, 97862), (97862, false));
+        assert_eq!(p.fen(), Pos::kiwipete().fen());
+    }
     #[test]
     fn depth_1() {
         setup();
-        let mut p = Pos::from_str(STARTPOS_FEN).unwrap();
+        let mut p = Pos::startpos();
         let u = p.perft::<true>(1);
         assert_eq!(u, 20);
+        assert_eq!(p.perft::<false>(1), 20);
     }
     #[test]
     fn depth_2() {
-        let mut p = Pos::from_str(STARTPOS_FEN).unwrap();
+        setup();
+        let mut p = Pos::startpos();
         let u = p.perft::<true>(2);
         assert_eq!(u, 400);
     }
     #[test]
     fn depth_3() {
-        let mut p = Pos::from_str(STARTPOS_FEN).unwrap();
+        setup();
+        let mut p = Pos::startpos();
         let u = p.perft::<true>(3);
         assert_eq!(u, 8902);
     }
     #[test]
     fn depth_4() {
-        let mut p = Pos::from_str(STARTPOS_FEN).unwrap();
+        setup();
+        let mut p = Pos::startpos();
         let u = p.perft::<true>(4);
         assert_eq!(u, 197_281);
     }
     #[test]
     fn depth_5() {
-        let mut p = Pos::from_str(STARTPOS_FEN).unwrap();
+        setup();
+        let mut p = Pos::startpos();
         assert_eq!(p.perft::<true>(5), 4_865_609);
     }
     #[test]
     #[ignore]
     fn depth_6() {
-        let mut p = Pos::from_str(STARTPOS_FEN).unwrap();
+        setup();
+        let mut p = Pos::startpos();
         assert_eq!(p.perft::<true>(6), 119_060_324);
     }
     #[test]
     #[ignore]
     fn depth_7() {
-        let mut p = Pos::from_str(STARTPOS_FEN).unwrap();
+        setup();
+        let mut p = Pos::startpos();
         assert_eq!(p.perft::<true>(7), 3_195_901_860);
     }
 
     #[test]
     fn kiwi_depth_1() {
-        let mut p = Pos::from_str(KIWI_FEN).unwrap();
+        setup();
+        let mut p = Pos::kiwipete();
         assert_eq!(p.perft::<true>(1), 48);
     }
     #[test]
     fn kiwi_depth_2() {
-        let mut p = Pos::from_str(KIWI_FEN).unwrap();
+        setup();
+        let mut p = Pos::kiwipete();
         assert_eq!(p.perft::<true>(2), 2039);
     }
     #[test]
     fn kiwi_depth_3() {
-        let mut p = Pos::from_str(KIWI_FEN).unwrap();
+        setup();
+        let mut p = Pos::kiwipete();
         assert_eq!(p.perft::<true>(3), 97_862);
     }
     #[test]
     fn kiwi_depth_4() {
-        let mut p = Pos::from_str(KIWI_FEN).unwrap();
+        setup();
+        let mut p = Pos::kiwipete();
         assert_eq!(p.perft::<true>(4), 4_085_603);
     }
     #[test]
     fn kiwi_depth_5() {
-        let mut p = Pos::from_str(KIWI_FEN).unwrap();
+        setup();
+        let mut p = Pos::kiwipete();
         assert_eq!(p.perft::<true>(5), 193_690_690);
     }
     #[test]
     #[ignore]
     fn kiwi_depth_6() {
-        let mut p = Pos::from_str(KIWI_FEN).unwrap();
+        setup();
+        let mut p = Pos::kiwipete();
         assert_eq!(p.perft::<true>(6), 8_031_647_685);
     }
 
     #[test]
     fn fen3_depth_1() {
+        setup();
         let mut p = Pos::from_str(P3_FEN).unwrap();
         assert_eq!(p.perft::<true>(1), 14);
     }
     #[test]
     fn fen3_depth_2() {
+        setup();
         let mut p = Pos::from_str(P3_FEN).unwrap();
         assert_eq!(p.perft::<true>(2), 191);
     }
     #[test]
     fn fen3_depth_3() {
+        setup();
         let mut p = Pos::from_str(P3_FEN).unwrap();
         assert_eq!(p.perft::<true>(3), 2812);
     }
     #[test]
     fn fen3_depth_4() {
+        setup();
         let mut p = Pos::from_str(P3_FEN).unwrap();
         assert_eq!(p.perft::<true>(4), 43_238);
     }
     #[test]
     fn fen3_depth_5() {
+        setup();
         let mut p = Pos::from_str(P3_FEN).unwrap();
         assert_eq!(p.perft::<true>(5), 674_624);
     }
+
+    // Lots of checks, so this leans on the evasion generator
+    const P4_FEN: &str = "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1";
+    #[test]
+    fn fen4_depth_3() {
+        setup();
+        let mut p = Pos::from_str(P4_FEN).unwrap();
+        assert_eq!(p.perft::<true>(3), 9_467);
+    }
+    #[test]
+    fn fen4_depth_4() {
+        setup();
+        let mut p = Pos::from_str(P4_FEN).unwrap();
+        assert_eq!(p.perft::<true>(4), 422_333);
+    }
+
+    // Black to promote on three files, with and without captures
+    const PROMO_FEN: &str = "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1";
+    #[test]
+    fn promo_depth_1() {
+        setup();
+        let mut p = Pos::from_str(PROMO_FEN).unwrap();
+        assert_eq!(p.perft::<true>(1), 24);
+
+        let mut moves = MoveList::new();
+        generate_legal::<true>(&p, &mut moves);
+        for pt in [PType::Knight, PType::Bishop, PType::Rook, PType::Queen] {
+            let promos = moves.as_slice().iter().filter(|m| m.kind() == MType::Promotion);
+            assert_eq!(promos.filter(|m| m.promo() == pt).count(), 3, "{pt:?}");
+        }
+    }
+    #[test]
+    fn promo_depth_2() {
+        setup();
+        let mut p = Pos::from_str(PROMO_FEN).unwrap();
+        assert_eq!(p.perft::<true>(2), 496);
+    }
+    #[test]
+    fn promo_depth_3() {
+        setup();
+        let mut p = Pos::from_str(PROMO_FEN).unwrap();
+        assert_eq!(p.perft::<true>(3), 9_483);
+    }
+    #[test]
+    fn promo_depth_4() {
+        setup();
+        let mut p = Pos::from_str(PROMO_FEN).unwrap();
+        assert_eq!(p.perft::<true>(4), 182_838);
+    }
+
+    #[test]
+    fn double_check_only_king_moves() {
+        setup();
+        let pos = Pos::from_str("4r1k1/8/8/8/8/3n4/R7/3QK3 w - - 0 1").unwrap();
+        assert!(pos.state().checkers().more_than_one());
+
+        let mut moves = MoveList::new();
+        generate_legal::<true>(&pos, &mut moves);
+        assert!(!moves.is_empty());
+        for i in 0..moves.len() {
+            assert_eq!(moves.get(i).from(), E1);
+        }
+
+        // Capturing one checker or blocking the other is not enough
+        assert!(!pos.is_legal(Move::new(D1, D3)));
+        assert!(!pos.is_legal(Move::new(A2, E2)));
+    }
 }
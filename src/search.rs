@@ -16,12 +16,17 @@
     along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
 
+use std::fmt;
+use std::time::{Duration, Instant};
+
 use crate::MAX_PLY;
 use crate::diagnostics;
-use crate::evaluate::{MAX_RAT, MIN_RAT};
+use crate::evaluate::{mated_in, Score, SearchOptions, DRAW, MAX_RAT, MIN_RAT};
 use crate::prelude::*;
 use crate::evaluate;
 use crate::moveorder::order_moves;
+use crate::time;
+use crate::tt;
 
 use crate::debug;
 use crate::prelude::individual_squares::*;
@@ -47,35 +52,460 @@ impl Line {
         self.moves[idx] = m;
     }
 
+    // Sets `head` as the first move, followed by the moves of `tail`.
+    // The tail is truncated so the line never exceeds MAX_PLY moves.
+    pub fn set_from(&mut self, head: Move, tail: &Line) {
+        let n = tail.len.min(MAX_PLY - 1);
+        self.moves[0] = head;
+        self.moves[1..=n].copy_from_slice(&tail.moves[..n]);
+        self.len = n + 1;
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
     #[inline]
     pub fn as_slice<'a>(&'a self) -> &'a [Move] {
         &self.moves[0..self.len]
     }
+
+    // Replays the line from `start`, Err holds the index of the first
+    // move that isn't legal where it is played
+    pub fn verify(&self, start: &Position) -> Result<(), usize> {
+        let mut pos = start.clone();
+        for (i, &m) in self.as_slice().iter().enumerate() {
+            if !pos.is_legal_checked(m) {
+                return Err(i);
+            }
+            pos.do_move(m);
+        }
+        Ok(())
+    }
+}
+
+// A legal move at the root and what the last iteration found for it
+#[derive(Debug, Clone)]
+pub struct RootMove {
+    pub mv: Move,
+    // MIN_RAT until the move raises alpha in the current iteration
+    pub score: i32,
+    pub prev_score: i32,
+    pub pv: Line,
+    // Whether the current iteration has got to this move yet
+    pub searched: bool,
+}
+
+// The root moves, best first. Sorted after every iteration so the
+// previous best is searched first at the next depth.
+#[derive(Debug, Clone)]
+pub struct RootMoves {
+    moves: Vec<RootMove>,
+}
+
+impl RootMoves {
+    pub fn new(pos: &Position) -> Self {
+        let moves = ordered_moves(pos, &[])
+            .into_iter()
+            .map(|mv| RootMove {
+                mv,
+                score: MIN_RAT,
+                prev_score: MIN_RAT,
+                pv: Line::new(),
+                searched: false,
+            })
+            .collect();
+        Self { moves }
+    }
+
+    pub fn len(&self) -> usize {
+        self.moves.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.moves.is_empty()
+    }
+    pub fn as_slice(&self) -> &[RootMove] {
+        &self.moves
+    }
+    pub fn best(&self) -> Option<&RootMove> {
+        self.moves.first()
+    }
+
+    fn start_iteration(&mut self) {
+        for rm in &mut self.moves {
+            rm.prev_score = rm.score;
+            rm.score = MIN_RAT;
+            rm.searched = false;
+        }
+    }
+
+    // Stable, so moves that failed low keep their relative order
+    pub fn sort(&mut self) {
+        self.moves.sort_by_key(|rm| std::cmp::Reverse(rm.score));
+    }
+}
+
+// Limits given by the UCI `go` command, times are in milliseconds
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SearchLimits {
+    pub depth: Option<usize>,
+    pub movetime: Option<u64>,
+    pub nodes: Option<u64>,
+    pub wtime: Option<u64>,
+    pub btime: Option<u64>,
+    pub winc: Option<u64>,
+    pub binc: Option<u64>,
+    pub movestogo: Option<u32>,
+    pub infinite: bool,
+}
+
+impl SearchLimits {
+    pub fn from_uci_go(tokens: &[&str]) -> Self {
+        let mut limits = Self::default();
+        let mut tokens = tokens.iter().copied().skip_while(|&t| t == "go");
+
+        while let Some(t) = tokens.next() {
+            match t {
+                "infinite" => limits.infinite = true,
+                "depth" => limits.depth = tokens.next().and_then(|v| v.parse().ok()),
+                "movetime" => limits.movetime = tokens.next().and_then(|v| v.parse().ok()),
+                "nodes" => limits.nodes = tokens.next().and_then(|v| v.parse().ok()),
+                "wtime" => limits.wtime = tokens.next().and_then(|v| v.parse().ok()),
+                "btime" => limits.btime = tokens.next().and_then(|v| v.parse().ok()),
+                "winc" => limits.winc = tokens.next().and_then(|v| v.parse().ok()),
+                "binc" => limits.binc = tokens.next().and_then(|v| v.parse().ok()),
+                "movestogo" => limits.movestogo = tokens.next().and_then(|v| v.parse().ok()),
+                _ => (),
+            }
+        }
+
+        limits
+    }
+
+    // Time to spend on this move, or None if the search isn't timed
+    pub fn allocated_time(&self, us: Color) -> Option<Duration> {
+        if self.infinite {
+            return None;
+        }
+        match time::allocate(self, us) {
+            Duration::MAX => None,
+            d => Some(d),
+        }
+    }
+}
+
+// Bookkeeping shared by every node of one search
+struct SearchInfo {
+    start: Instant,
+    hard_limit: Option<Duration>,
+    max_nodes: Option<u64>,
+    nodes: u64,
+    stopped: bool,
+    // Deepest ply reached this iteration, quiescence included
+    seldepth: usize,
+    // Pre-root game keys, followed by the keys along the current search path
+    history: Vec<Key>,
+    root_len: usize,
+    options: SearchOptions,
+}
+
+impl SearchInfo {
+    // How often (in nodes) the clock is looked at
+    const CHECK_INTERVAL: u64 = 1024;
+
+    fn new(
+        hard_limit: Option<Duration>,
+        max_nodes: Option<u64>,
+        root_history: &[Key],
+        options: SearchOptions,
+    ) -> Self {
+        Self {
+            start: Instant::now(),
+            hard_limit,
+            max_nodes,
+            nodes: 0,
+            stopped: false,
+            seldepth: 0,
+            history: root_history.to_vec(),
+            root_len: root_history.len(),
+            options,
+        }
+    }
+
+    // A repeat inside the search tree is scored as a draw straight away,
+    // but one reaching back past the root needs to be a real threefold.
+    fn is_repetition(&self, pos: &Position) -> bool {
+        let key = pos.state().key();
+        let window = (pos.state().rule50() as usize).min(self.history.len());
+        let mut count = 0;
+
+        for i in (self.history.len() - window..self.history.len()).rev() {
+            if self.history[i] == key {
+                if i >= self.root_len {
+                    return true;
+                }
+                count += 1;
+                if count >= 2 {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+    // Plies from the root to the node being searched
+    fn ply(&self) -> usize {
+        self.history.len() - self.root_len
+    }
+
+    fn visit(&mut self) -> bool {
+        self.nodes += 1;
+        self.seldepth = self.seldepth.max(self.ply());
+        if self.max_nodes.is_some_and(|n| self.nodes >= n) {
+            self.stopped = true;
+        }
+        if self.nodes.is_multiple_of(Self::CHECK_INTERVAL)
+            && self.hard_limit.is_some_and(|h| self.start.elapsed() >= h)
+        {
+            self.stopped = true;
+        }
+        self.stopped
+    }
+}
+
+// What one finished iteration found, shown as a UCI info line
+#[derive(Debug, Clone)]
+pub struct IterationReport {
+    pub depth: usize,
+    pub seldepth: usize,
+    pub score: Score,
+    pub nodes: u64,
+    pub time: Duration,
+    pub pv: Line,
+}
+
+impl fmt::Display for IterationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "info depth {} seldepth {} score {} nodes {} time {} pv",
+            self.depth,
+            self.seldepth,
+            self.score,
+            self.nodes,
+            self.time.as_millis()
+        )?;
+        for m in self.pv.as_slice() {
+            write!(f, " {m}")?;
+        }
+        Ok(())
+    }
+}
+
+// State that outlives a single search
+#[derive(Debug, Default, Clone)]
+pub struct Searcher {
+    root_history: Vec<Key>,
+    options: SearchOptions,
+}
+
+impl Searcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Keys of the positions actually played before the root, oldest first,
+    // so repetitions spanning the root are seen
+    pub fn set_root_history(&mut self, keys: &[Key]) {
+        self.root_history = keys.to_vec();
+    }
+
+    pub fn set_options(&mut self, options: SearchOptions) {
+        self.options = options;
+    }
+
+    // Iterative deepening until the depth, node or time limits are hit.
+    // A new depth is only started while within the soft time budget.
+    pub fn search(&self, pos: &mut Position, limits: &SearchLimits) -> (Line, i32) {
+        iterative_deepening_from(pos, limits, &self.root_history, &self.options, &mut |_| ())
+    }
+
+    // search, handing `report` the result of every finished iteration
+    pub fn search_with_report(
+        &self,
+        pos: &mut Position,
+        limits: &SearchLimits,
+        mut report: impl FnMut(&IterationReport),
+    ) -> (Line, i32) {
+        iterative_deepening_from(pos, limits, &self.root_history, &self.options, &mut report)
+    }
+}
+
+pub fn iterative_deepening(pos: &mut Position, limits: &SearchLimits) -> (Line, i32) {
+    iterative_deepening_from(pos, limits, &[], &SearchOptions::DEFAULT, &mut |_| ())
+}
+
+fn iterative_deepening_from(
+    pos: &mut Position,
+    limits: &SearchLimits,
+    root_history: &[Key],
+    options: &SearchOptions,
+    report: &mut dyn FnMut(&IterationReport),
+) -> (Line, i32) {
+    let us = pos.to_move();
+    let soft = limits.allocated_time(us);
+    let hard = soft.map(|_| time::hard_limit(limits, us));
+    let max_depth = limits.depth.unwrap_or(MAX_PLY - 1).min(MAX_PLY - 1);
+
+    let mut info = SearchInfo::new(hard, limits.nodes, root_history, *options);
+    let mut root_moves = RootMoves::new(pos);
+    let mut best = (Line::new(), 0);
+    tt::new_search();
+
+    for depth in 1..=max_depth {
+        info.seldepth = 0;
+        let eval = search_root(pos, depth, &mut root_moves, &mut info);
+        let line = root_moves.best().map_or(Line::new(), |rm| rm.pv);
+
+        // An aborted iteration is only trusted if nothing else was finished
+        if info.stopped {
+            if best.0.len() == 0 {
+                best = (line, eval);
+            }
+            break;
+        }
+        best = (line, eval);
+        report(&IterationReport {
+            depth,
+            seldepth: info.seldepth,
+            score: Score(eval),
+            nodes: info.nodes,
+            time: info.start.elapsed(),
+            pv: line,
+        });
+
+        if soft.is_some_and(|s| info.start.elapsed() >= s) {
+            break;
+        }
+    }
+
+    best
 }
 
 pub fn ab_with_pv(pos: &mut Position, depth: usize) -> (Line, i32) {
-    let c = pos.to_move();
     let mut l = Line::new();
-    let eval = ab_compile_lines(pos, depth, c.persp(MIN_RAT), c.persp(MAX_RAT), &mut l);
+    let mut info = SearchInfo::new(None, None, &[], SearchOptions::DEFAULT);
+    let eval = ab_compile_lines(pos, depth, MIN_RAT, MAX_RAT, &mut l, &mut info, &[]);
     (l, eval)
 }
 
-fn ab_compile_lines(pos: &mut Position, depth: usize, alpha: i32, beta: i32, pv: &mut Line) -> i32 {
+// One iteration over the root moves, which are sorted again afterwards.
+// Each move's own PV from earlier iterations guides the search below it.
+fn search_root(
+    pos: &mut Position,
+    depth: usize,
+    root_moves: &mut RootMoves,
+    info: &mut SearchInfo,
+) -> i32 {
+    if root_moves.is_empty() {
+        return if pos.in_check() { mated_in(0) } else { DRAW };
+    }
+    let mut alpha = MIN_RAT;
+    root_moves.start_iteration();
+
+    if !info.visit() {
+        let mut line = Line::new();
+        for rm in &mut root_moves.moves {
+            line.clear();
+            let prev_pv = rm.pv;
+            let child_pv = prev_pv.as_slice().get(1..).unwrap_or(&[]);
+            info.history.push(pos.state().key());
+            pos.do_move(rm.mv);
+            let e = -ab_compile_lines(pos, depth - 1, MIN_RAT, -alpha, &mut line, info, child_pv);
+            pos.undo_move(rm.mv);
+            info.history.pop();
+
+            if info.stopped {
+                break;
+            }
+            rm.searched = true;
+            if e > alpha {
+                alpha = e;
+                rm.score = e;
+                rm.pv.set_from(rm.mv, &line);
+            }
+        }
+    }
+
+    root_moves.sort();
+    alpha
+}
+
+// Ordered moves, with the previous PV's move for this node (if it is
+// still being followed and legal here) ahead of everything else
+fn ordered_moves(pos: &Position, prev_pv: &[Move]) -> MoveList {
     let mut move_list = MoveList::new();
+    generate_legal::<false>(pos, &mut move_list);
+    order_moves(pos, &mut move_list);
+    if let Some(&m) = prev_pv.first() {
+        move_list.move_to_front(m);
+    }
+    move_list
+}
+
+// `prev_pv` is the rest of the previous iteration's PV while this node is
+// on it, and empty once the search has left it
+fn ab_compile_lines(
+    pos: &mut Position,
+    depth: usize,
+    alpha: i32,
+    beta: i32,
+    pv: &mut Line,
+    info: &mut SearchInfo,
+    prev_pv: &[Move],
+) -> i32 {
     let mut line = Line::new();
     let mut alpha = alpha;
 
+    if info.visit() {
+        return alpha;
+    }
+
+    if info.history.len() > info.root_len && info.is_repetition(pos) {
+        return DRAW;
+    }
+
     if depth == 0 {
-        return evaluate::quiescence(pos, alpha, beta);
+        let (score, qdepth) =
+            evaluate::quiescence_with_depth(pos, alpha, beta, info.ply(), &info.options);
+        info.seldepth = info.seldepth.max(info.ply() + qdepth);
+        return score;
     }
 
-    generate_legal::<false>(pos, &mut move_list);
-    order_moves(pos, &mut move_list);
+    let move_list = ordered_moves(pos, prev_pv);
+    if move_list.is_empty() {
+        return if pos.in_check() { mated_in(info.ply()) } else { DRAW };
+    }
     for i in 0..move_list.len() {
         let m = move_list.get(i);
+        // Children that return early don't touch the line, a previous
+        // sibling's moves must not end up in the PV
+        line.clear();
+        info.history.push(pos.state().key());
         pos.do_move(m);
-        let e = -ab_compile_lines(pos, depth - 1, -beta, -alpha, &mut line);
+        let child_pv = match prev_pv.split_first() {
+            Some((&head, tail)) if head == m => tail,
+            _ => &[],
+        };
+        let e = -ab_compile_lines(pos, depth - 1, -beta, -alpha, &mut line, info, child_pv);
         pos.undo_move(m);
+        info.history.pop();
+
+        if info.stopped {
+            return alpha;
+        }
 
         if e >= beta {
             diagnostics::add_beta_cutoffs();
@@ -83,16 +513,321 @@ fn ab_compile_lines(pos: &mut Position, depth: usize, alpha: i32, beta: i32, pv:
         }
         if e > alpha {
             alpha = e;
-            pv.len = line.len() + 1;
-            pv.set(0, m);
-            let src = &line.moves[0] as *const _;
-            let dst = &mut pv.moves[1] as *mut _;
-            let count = line.len();
-            unsafe {
-                std::ptr::copy(src, dst, count);
-            }
+            pv.set_from(m, &line);
         }
     }
 
     alpha
 }
+
+// Shortest forced mate for the side to move within `max_ply` plies. The
+// attacker only tries checks and captures (everything when in check
+// itself), the defender every legal reply.
+pub fn find_mate(pos: &mut Position, max_ply: usize) -> Option<Line> {
+    (1..=max_ply.min(MAX_PLY)).step_by(2).find_map(|plies| {
+        let mut line = Line::new();
+        mate_attack(pos, plies, &mut line).then_some(line)
+    })
+}
+
+fn mate_attack(pos: &mut Position, plies: usize, line: &mut Line) -> bool {
+    let mut move_list = MoveList::new();
+    if pos.in_check() {
+        generate_for_legal(pos, GenType::Evasions, &mut move_list);
+    } else {
+        generate_for_legal(pos, GenType::Captures, &mut move_list);
+        generate_for_legal(pos, GenType::QuietChecks, &mut move_list);
+    }
+
+    let mut tail = Line::new();
+    for m in move_list {
+        tail.clear();
+        pos.do_move(m);
+        let mates = mate_defend(pos, plies - 1, &mut tail);
+        pos.undo_move(m);
+
+        if mates {
+            line.set_from(m, &tail);
+            return true;
+        }
+    }
+    false
+}
+
+// Whether every reply still gets mated, `line` keeps the longest defence
+fn mate_defend(pos: &mut Position, plies: usize, line: &mut Line) -> bool {
+    let replies = pos.legal_moves();
+    if replies.is_empty() {
+        return pos.in_check();
+    }
+    if plies < 2 {
+        return false;
+    }
+
+    let mut tail = Line::new();
+    for m in replies {
+        tail.clear();
+        pos.do_move(m);
+        let mated = mate_attack(pos, plies - 1, &mut tail);
+        pos.undo_move(m);
+
+        if !mated {
+            return false;
+        }
+        if tail.len() + 1 > line.len() {
+            line.set_from(m, &tail);
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn set_from_prepends_head() {
+        let mut tail = Line::new();
+        tail.set(0, Move::new(E7, E5));
+        tail.set(1, Move::new(G1, F3));
+
+        let mut pv = Line::new();
+        pv.set_from(Move::new(E2, E4), &tail);
+        assert_eq!(pv.len(), 3);
+        assert_eq!(
+            pv.as_slice(),
+            &[Move::new(E2, E4), Move::new(E7, E5), Move::new(G1, F3)]
+        );
+    }
+
+    #[test]
+    fn parse_go_with_clock() {
+        let tokens = "go wtime 300000 btime 300000 movestogo 40"
+            .split_whitespace()
+            .collect::<Vec<_>>();
+        let limits = SearchLimits::from_uci_go(&tokens);
+
+        assert_eq!(limits.wtime, Some(300_000));
+        assert_eq!(limits.btime, Some(300_000));
+        assert_eq!(limits.movestogo, Some(40));
+        assert_eq!(limits.depth, None);
+        assert!(!limits.infinite);
+        assert_eq!(
+            limits.allocated_time(Color::White),
+            Some(Duration::from_millis(7500))
+        );
+    }
+
+    #[test]
+    fn iterative_deepening_respects_depth() {
+        crate::init_for_tests();
+        let mut p = Position::from_str("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1").unwrap();
+        let limits = SearchLimits::from_uci_go(&["go", "depth", "3"]);
+        let (line, _) = iterative_deepening(&mut p, &limits);
+        assert_eq!(line.as_slice()[0], Move::new(D1, D5));
+        assert_eq!(line.verify(&p), Ok(()));
+    }
+
+    #[test]
+    fn searched_lines_are_legal() {
+        crate::init_for_tests();
+        for fen in [
+            Position::STARTPOS,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        ] {
+            let mut p = Position::from_str(fen).unwrap();
+            for depth in 1..=3 {
+                let (line, _) = ab_with_pv(&mut p, depth);
+                assert!(line.len() > 0);
+                assert_eq!(line.verify(&p), Ok(()), "{fen} at depth {depth}");
+            }
+        }
+    }
+
+    #[test]
+    fn corrupted_line_is_caught() {
+        crate::init_for_tests();
+        let p = Position::startpos();
+        let mut line = Line::new();
+        line.set(0, Move::new(E2, E4));
+        line.set(1, Move::new(E7, E5));
+        assert_eq!(line.verify(&p), Ok(()));
+
+        // White moved twice in a row
+        line.set(2, Move::new(E4, E5));
+        line.set(3, Move::new(G1, F3));
+        assert_eq!(line.verify(&p), Err(2));
+    }
+
+    #[test]
+    fn previous_pv_move_is_tried_first() {
+        crate::init_for_tests();
+        let p = Position::startpos();
+        let plain = ordered_moves(&p, &[]);
+        let pv = [Move::new(A2, A3), Move::new(E7, E5)];
+        assert_ne!(plain.get(0), pv[0]);
+
+        let ordered = ordered_moves(&p, &pv);
+        assert_eq!(ordered.get(0), pv[0]);
+        assert_eq!(ordered.len(), plain.len());
+        // Everything else keeps its order
+        let rest = plain.as_slice().iter().filter(|&&m| m != pv[0]).collect::<Vec<_>>();
+        assert_eq!(ordered.as_slice()[1..].iter().collect::<Vec<_>>(), rest);
+
+        // A PV move that isn't legal here changes nothing
+        let ordered = ordered_moves(&p, &[Move::new(E7, E5)]);
+        assert_eq!(ordered.as_slice(), plain.as_slice());
+
+        let mut p = Position::from_str("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1").unwrap();
+        let limits = SearchLimits::from_uci_go(&["go", "depth", "4"]);
+        let (line, _) = iterative_deepening(&mut p, &limits);
+        assert_eq!(line.as_slice()[0], Move::new(D1, D5));
+    }
+
+    #[test]
+    fn root_moves_are_sorted_between_iterations() {
+        crate::init_for_tests();
+        // Rxd5 is the only move that doesn't drop material, and the
+        // initial ordering doesn't put it first
+        let mut p = Position::from_str("4k3/8/8/3q4/8/8/P7/3RK3 w - - 0 1").unwrap();
+        let mut root_moves = RootMoves::new(&p);
+        let mut info = SearchInfo::new(None, None, &[], SearchOptions::DEFAULT);
+        assert_eq!(root_moves.len(), p.legal_moves().len());
+
+        search_root(&mut p, 1, &mut root_moves, &mut info);
+        let eval = search_root(&mut p, 2, &mut root_moves, &mut info);
+        let best = root_moves.best().unwrap();
+        assert_eq!(best.mv, Move::new(D1, D5));
+        assert_eq!(best.score, eval);
+        assert_eq!(best.pv.as_slice()[0], best.mv);
+        let moves = root_moves.as_slice();
+        assert!(moves.iter().all(|rm| rm.searched));
+        assert!(moves.windows(2).all(|w| w[0].score >= w[1].score));
+
+        // The depth 3 search starts from the depth 2 order
+        search_root(&mut p, 3, &mut root_moves, &mut info);
+        let best = root_moves.best().unwrap();
+        assert_eq!(best.mv, Move::new(D1, D5));
+        assert_eq!(best.prev_score, eval);
+    }
+
+    #[test]
+    fn seldepth_counts_quiescence() {
+        crate::init_for_tests();
+        // Plenty of captures around e5 for quiescence to follow
+        let mut p = Position::from_str(
+            "1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1",
+        )
+        .unwrap();
+        let limits = SearchLimits::from_uci_go(&["go", "depth", "3"]);
+        let mut reports = Vec::new();
+        Searcher::new().search_with_report(&mut p, &limits, |r| reports.push(r.clone()));
+
+        assert_eq!(reports.len(), 3);
+        for (r, depth) in reports.iter().zip(1..) {
+            assert_eq!(r.depth, depth);
+            assert!(r.seldepth >= r.depth, "{r}");
+        }
+        assert!(reports.iter().any(|r| r.seldepth > r.depth));
+
+        let line = reports[2].to_string();
+        assert!(line.starts_with("info depth 3 seldepth "), "{line}");
+        assert!(line.contains(&format!(" pv {}", reports[2].pv.as_slice()[0])));
+    }
+
+    #[test]
+    fn mate_scores_count_from_the_root() {
+        crate::init_for_tests();
+        let score = |fen| {
+            let mut p = Position::from_str(fen).unwrap();
+            let limits = SearchLimits::from_uci_go(&["go", "depth", "4"]);
+            let mut reports = Vec::new();
+            Searcher::new().search_with_report(&mut p, &limits, |r| reports.push(r.clone()));
+            reports.last().unwrap().score.to_string()
+        };
+
+        // Ra8#, and Kg8 Rb8# against the side to move
+        assert_eq!(score("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1"), "mate 1");
+        assert_eq!(score("7k/R7/1R6/8/8/8/8/6K1 b - - 0 1"), "mate -1");
+        // Already mated, and stalemated
+        assert_eq!(score("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1"), "mate 0");
+        assert_eq!(score("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1"), "cp 0");
+    }
+
+    #[test]
+    fn finds_smothered_mate_in_three() {
+        crate::init_for_tests();
+        // Nh6+ Kh8 Qg8+ Rxg8 Nf7#
+        let mut p = Position::from_str("r5k1/5Npp/8/8/8/1Q6/8/6K1 w - - 0 1").unwrap();
+        assert!(find_mate(&mut p, 3).is_none());
+
+        let line = find_mate(&mut p, 7).unwrap();
+        assert_eq!(line.len(), 5);
+        assert_eq!(line.verify(&p), Ok(()));
+        assert_eq!(line.as_slice()[0], Move::new(F7, H6));
+
+        let mut end = p.clone();
+        for &m in line.as_slice() {
+            end.do_move(m);
+        }
+        assert!(end.in_check() && end.legal_moves().is_empty());
+
+        let mut p = Position::from_str("7k/8/5K2/8/8/8/8/6Q1 w - - 0 1").unwrap();
+        assert_eq!(find_mate(&mut p, 1).unwrap().as_slice(), &[Move::new(G1, G7)]);
+        let mut p = Position::from_str("7k/8/6K1/8/8/8/8/8 w - - 0 1").unwrap();
+        assert!(find_mate(&mut p, 5).is_none());
+    }
+
+    #[test]
+    fn repetition_across_root_is_a_draw() {
+        crate::init_for_tests();
+        // Down a queen, White is happy to repeat with Ng1
+        let mut p = Position::from_str("7k/q7/8/8/8/5N2/8/4K3 w - - 0 1").unwrap();
+        let shuffle = [
+            Move::new(F3, G1),
+            Move::new(A7, B7),
+            Move::new(G1, F3),
+            Move::new(B7, A7),
+        ];
+
+        let mut keys = Vec::new();
+        for m in shuffle.iter().chain(shuffle.iter()) {
+            keys.push(p.state().key());
+            p.do_move(*m);
+        }
+
+        let limits = SearchLimits::from_uci_go(&["go", "depth", "1"]);
+        let (_, eval) = Searcher::new().search(&mut p, &limits);
+        assert!(eval < 0);
+
+        // After Ng1 the position has now occurred three times
+        let mut searcher = Searcher::new();
+        searcher.set_root_history(&keys);
+        let (line, eval) = searcher.search(&mut p, &limits);
+        assert_eq!(eval, 0);
+        assert_eq!(line.as_slice()[0], Move::new(F3, G1));
+        assert_eq!(line.verify(&p), Ok(()));
+
+        // Only one earlier occurrence is not yet a draw
+        searcher.set_root_history(&keys[4..]);
+        let (_, eval) = searcher.search(&mut p, &limits);
+        assert!(eval < 0);
+    }
+
+    #[test]
+    fn set_from_truncates_full_line() {
+        let mut tail = Line::new();
+        for i in 0..MAX_PLY {
+            tail.set(i, Move::new(E7, E5));
+        }
+        assert_eq!(tail.len(), MAX_PLY);
+
+        let mut pv = Line::new();
+        pv.set_from(Move::new(E2, E4), &tail);
+        assert_eq!(pv.len(), MAX_PLY);
+        assert_eq!(pv.as_slice()[0], Move::new(E2, E4));
+        assert_eq!(pv.as_slice()[MAX_PLY - 1], Move::new(E7, E5));
+    }
+}
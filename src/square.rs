@@ -50,27 +50,20 @@ impl Square {
         }
     }
 
-    // Squash it into the relative file left side
-    pub fn weight_map_idx(self, color: Color) -> usize {
-        // To make sure that we can "see" the table upside down
-        // and the squares are correctly mapped
-        let flip_vert_xor = match color {
-            Color::White => 0,
-            Color::Black => 56
-        };
+    // The rank as seen from `color`'s side of the board
+    pub const fn relative_rank(self, color: Color) -> Rank {
+        self.relative(color).rank()
+    }
 
-        let flip_horiz_xor = if self.file() >= File::E {
-            7
+    // Squash it into the relative file left side, so a 32 entry table
+    // covers the whole board for either color
+    pub fn weight_map_idx(self, color: Color) -> usize {
+        let file = if self.file() >= File::E {
+            self.file().flip()
         } else {
-            0
+            self.file()
         };
-
-        // Now this has to be convert to a 0..31 index
-        // instead of the usual 0..63 index
-        let correct_square = self.0 as usize ^ flip_vert_xor ^ flip_horiz_xor;
-        let f = correct_square & 7;
-        let r = correct_square >> 3;
-        4 * r + f
+        4 * self.relative_rank(color) as usize + file as usize
     }
 
     pub const fn file(self) -> File {
@@ -88,6 +81,12 @@ impl Square {
         (line(self, other1) & other2).nonzero()
     }
 
+    // Whether all three squares lie on one rank, file or diagonal
+    #[inline]
+    pub fn aligned(a: Self, b: Self, c: Self) -> bool {
+        (line(a, b) & c).nonzero()
+    }
+
     pub fn dist(self, other: Self) -> u32 {
         // Unsigned distance (king moves)
         let fd = (self.file() as u32).abs_diff(other.file() as u32);
@@ -140,3 +139,38 @@ pub mod individual_squares {
         G6, H6, A7, B7, C7, D7, E7, F7, G7, H7, A8, B8, C8, D8, E8, F8, G8, H8
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::individual_squares::*;
+    use super::{Color, Rank, Square};
+
+    #[test]
+    fn aligned_squares() {
+        crate::init_for_tests();
+        assert!(Square::aligned(A1, B2, C3));
+        assert!(Square::aligned(C3, A1, H8));
+        assert!(Square::aligned(A1, A8, A4));
+        assert!(!Square::aligned(A1, B2, C4));
+        assert!(!Square::aligned(A1, B3, C5));
+    }
+
+    #[test]
+    fn relative_ranks() {
+        assert_eq!(E7.relative_rank(Color::White), Rank::Seven);
+        assert_eq!(E2.relative_rank(Color::Black), Rank::Seven);
+        assert_eq!(A1.relative_rank(Color::Black), Rank::Eight);
+        assert_eq!(H4.relative_rank(Color::White), Rank::Four);
+    }
+
+    #[test]
+    fn weight_map_folds_files() {
+        assert_eq!(A1.weight_map_idx(Color::White), 0);
+        assert_eq!(H1.weight_map_idx(Color::White), 0);
+        assert_eq!(E2.weight_map_idx(Color::White), 7);
+        assert_eq!(D7.weight_map_idx(Color::Black), 7);
+        assert_eq!(A8.weight_map_idx(Color::Black), 0);
+        assert_eq!(H8.weight_map_idx(Color::White), 28);
+        assert_eq!(D8.weight_map_idx(Color::White), 31);
+    }
+}
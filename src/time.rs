@@ -0,0 +1,100 @@
+/*
+    ChessLib, a UCI chess engine
+    Copyright (C) 2023 Sam Price
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::time::Duration;
+
+use crate::prelude::Color;
+use crate::search::SearchLimits;
+
+// Assumed number of moves left when `movestogo` isn't given
+const MOVES_TO_GO_ESTIMATE: u64 = 30;
+// Kept back for GUI and communication lag, in milliseconds
+const MOVE_OVERHEAD: u64 = 50;
+// Share of the increment we are willing to spend, in percent
+const INCREMENT_PERCENT: u64 = 75;
+// The hard limit may stretch the soft budget this many times
+const HARD_LIMIT_FACTOR: u64 = 4;
+// Never plan to use more than this share of the clock on one move, in percent
+const MAX_CLOCK_PERCENT: u64 = 50;
+
+fn clock(limits: &SearchLimits, to_move: Color) -> Option<(u64, u64)> {
+    match to_move {
+        Color::White => Some((limits.wtime?, limits.winc.unwrap_or(0))),
+        Color::Black => Some((limits.btime?, limits.binc.unwrap_or(0))),
+    }
+}
+
+// Soft budget: the search shouldn't start a new depth after this much time
+pub fn allocate(limits: &SearchLimits, to_move: Color) -> Duration {
+    if let Some(ms) = limits.movetime {
+        return Duration::from_millis(ms.saturating_sub(MOVE_OVERHEAD).max(1));
+    }
+    let Some((remaining, inc)) = clock(limits, to_move) else {
+        return Duration::MAX;
+    };
+
+    let moves = limits
+        .movestogo
+        .map_or(MOVES_TO_GO_ESTIMATE, |m| m.max(1) as u64);
+    let usable = remaining.saturating_sub(MOVE_OVERHEAD);
+    let budget = remaining / moves + inc * INCREMENT_PERCENT / 100;
+
+    Duration::from_millis(budget.min(usable * MAX_CLOCK_PERCENT / 100).max(1))
+}
+
+// Hard cap: the search is aborted mid-iteration past this much time
+pub fn hard_limit(limits: &SearchLimits, to_move: Color) -> Duration {
+    if let Some(ms) = limits.movetime {
+        return Duration::from_millis(ms.saturating_sub(MOVE_OVERHEAD).max(1));
+    }
+    let Some((remaining, _)) = clock(limits, to_move) else {
+        return Duration::MAX;
+    };
+
+    let usable = remaining.saturating_sub(MOVE_OVERHEAD).max(1);
+    let soft = allocate(limits, to_move).as_millis() as u64;
+    Duration::from_millis((soft * HARD_LIMIT_FACTOR).min(usable))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn five_minutes_forty_moves() {
+        let limits = SearchLimits {
+            wtime: Some(300_000),
+            btime: Some(300_000),
+            movestogo: Some(40),
+            ..Default::default()
+        };
+
+        let soft = allocate(&limits, Color::White);
+        assert!(soft >= Duration::from_secs(7) && soft <= Duration::from_secs(8));
+        assert!(hard_limit(&limits, Color::White) >= soft);
+
+        let low = SearchLimits {
+            btime: Some(100),
+            binc: Some(5_000),
+            movestogo: Some(1),
+            ..Default::default()
+        };
+        assert!(allocate(&low, Color::Black) < Duration::from_millis(100));
+        assert!(hard_limit(&low, Color::Black) < Duration::from_millis(100));
+    }
+}
@@ -15,3 +15,251 @@
     You should have received a copy of the GNU General Public License
     along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
+
+use std::mem::size_of;
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::prelude::{Key, Move};
+
+pub const DEFAULT_TT_MB: usize = 16;
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Bound {
+    #[default]
+    None,
+    Exact,
+    Lower,
+    Upper,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Entry {
+    key: Key,
+    pub best_move: Move,
+    pub score: i32,
+    pub depth: u8,
+    pub bound: Bound,
+    // Search generation the entry was written in
+    generation: u8,
+}
+
+// Entries whose keys map to the same slot share a cluster
+const CLUSTER_SIZE: usize = 4;
+const AGE_PENALTY: i32 = 8;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Cluster {
+    entries: [Entry; CLUSTER_SIZE],
+}
+
+#[derive(Debug, Default)]
+pub struct TranspositionTable {
+    clusters: Vec<Cluster>,
+    generation: u8,
+}
+
+impl TranspositionTable {
+    pub const fn empty() -> Self {
+        Self {
+            clusters: Vec::new(),
+            generation: 0,
+        }
+    }
+
+    pub fn new(mb: usize) -> Self {
+        let n = (mb * 1024 * 1024 / size_of::<Cluster>()).max(1);
+        Self {
+            clusters: vec![Cluster::default(); n],
+            generation: 0,
+        }
+    }
+
+    // Entries from earlier searches become cheaper to replace
+    pub fn new_search(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    // How much an entry is worth keeping, every search since it was
+    // written costs it AGE_PENALTY plies of depth
+    fn replace_score(&self, e: &Entry) -> i32 {
+        let age = self.generation.wrapping_sub(e.generation) as i32;
+        e.depth as i32 - AGE_PENALTY * age
+    }
+
+    // Drops every entry
+    pub fn resize(&mut self, mb: usize) {
+        *self = Self::new(mb);
+    }
+
+    #[inline]
+    fn index(&self, key: Key) -> usize {
+        // Maps the key onto the table without a modulo
+        ((key.0 as u128 * self.clusters.len() as u128) >> 64) as usize
+    }
+
+    pub fn probe(&self, key: Key) -> Option<Entry> {
+        if self.clusters.is_empty() {
+            return None;
+        }
+        self.clusters[self.index(key)]
+            .entries
+            .iter()
+            .find(|e| e.bound != Bound::None && e.key == key)
+            .copied()
+    }
+
+    // Overwrites the entry for the same key, else fills an empty slot,
+    // else evicts the entry with the lowest replace_score. Anything that
+    // isn't a real move is kept as Move::null_move().
+    pub fn store(&mut self, key: Key, best_move: Move, score: i32, depth: usize, bound: Bound) {
+        if self.clusters.is_empty() {
+            return;
+        }
+        let best_move = if best_move.is_ok() { best_move } else { Move::null_move() };
+        let i = self.index(key);
+        let cluster = &self.clusters[i].entries;
+        let slot = match cluster.iter().position(|e| e.bound == Bound::None || e.key == key) {
+            Some(j) => j,
+            None => (0..CLUSTER_SIZE)
+                .min_by_key(|&j| self.replace_score(&cluster[j]))
+                .unwrap(),
+        };
+        self.clusters[i].entries[slot] = Entry {
+            key,
+            best_move,
+            score,
+            depth: depth.min(u8::MAX as usize) as u8,
+            bound,
+            generation: self.generation,
+        };
+    }
+
+    pub fn clear(&mut self) {
+        self.clusters.fill(Cluster::default());
+    }
+
+    // Number of entries, not clusters
+    pub fn len(&self) -> usize {
+        self.clusters.len() * CLUSTER_SIZE
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clusters.is_empty()
+    }
+}
+
+// The engine-wide table used by the search. Searches running at the same
+// time (the tests do) take turns through the lock.
+static TT: RwLock<TranspositionTable> = RwLock::new(TranspositionTable::empty());
+
+// A panic mid-store leaves at worst one bad entry, which probes tolerate
+fn table() -> RwLockReadGuard<'static, TranspositionTable> {
+    TT.read().unwrap_or_else(PoisonError::into_inner)
+}
+fn table_mut() -> RwLockWriteGuard<'static, TranspositionTable> {
+    TT.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub fn probe(key: Key) -> Option<Entry> {
+    table().probe(key)
+}
+pub fn store(key: Key, best_move: Move, score: i32, depth: usize, bound: Bound) {
+    table_mut().store(key, best_move, score, depth, bound);
+}
+pub fn clear() {
+    table_mut().clear();
+}
+pub fn new_search() {
+    table_mut().new_search();
+}
+// Drops every entry, a search running meanwhile just loses its hash moves
+pub fn resize(mb: usize) {
+    table_mut().resize(mb);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::individual_squares::*;
+
+    #[test]
+    fn store_then_probe() {
+        let mut tt = TranspositionTable::new(1);
+        let key = Key(0xdead_beef_1234_5678);
+        let m = Move::new(E2, E4);
+
+        assert!(tt.probe(key).is_none());
+        tt.store(key, m, 35, 4, Bound::Exact);
+        let e = tt.probe(key).unwrap();
+        assert_eq!(e.best_move, m);
+        assert_eq!(e.score, 35);
+        assert_eq!(e.depth, 4);
+        assert_eq!(e.bound, Bound::Exact);
+        assert!(tt.probe(Key(key.0 ^ 1)).is_none());
+
+        tt.clear();
+        assert!(tt.probe(key).is_none());
+    }
+
+    #[test]
+    fn null_best_move_round_trips() {
+        let mut tt = TranspositionTable::new(1);
+        let key = Key(0x1234_5678_9abc_def0);
+        tt.store(key, Move::null_move(), -20, 3, Bound::Upper);
+        assert!(tt.probe(key).unwrap().best_move.is_null());
+
+        // An unset move is stored as the sentinel too
+        tt.store(key, Move::NULL, -20, 3, Bound::Upper);
+        assert!(tt.probe(key).unwrap().best_move.is_null());
+    }
+
+    #[test]
+    fn full_cluster_evicts_shallowest() {
+        let mut tt = TranspositionTable::new(1);
+        // Same high bits, so all of these land in one cluster
+        let keys = (0..=CLUSTER_SIZE as u64)
+            .map(|i| Key(0x4242_0000_0000_0000 | i))
+            .collect::<Vec<_>>();
+        let m = Move::new(G1, F3);
+
+        for (i, &key) in keys[..CLUSTER_SIZE].iter().enumerate() {
+            tt.store(key, m, 0, [5, 2, 7, 4][i], Bound::Exact);
+        }
+        assert!(keys[..CLUSTER_SIZE].iter().all(|&k| tt.probe(k).is_some()));
+
+        tt.store(keys[CLUSTER_SIZE], m, 0, 1, Bound::Lower);
+        assert_eq!(tt.probe(keys[CLUSTER_SIZE]).unwrap().bound, Bound::Lower);
+        assert!(tt.probe(keys[1]).is_none());
+        assert!([0, 2, 3].iter().all(|&i| tt.probe(keys[i]).is_some()));
+
+        // The same key is updated in place rather than evicting another
+        tt.store(keys[0], m, 10, 1, Bound::Upper);
+        assert_eq!(tt.probe(keys[0]).unwrap().score, 10);
+        assert!([2, 3, CLUSTER_SIZE].iter().all(|&i| tt.probe(keys[i]).is_some()));
+
+        tt.resize(2);
+        assert!(tt.probe(keys[0]).is_none());
+        assert_eq!(tt.len(), 2 * 1024 * 1024 / size_of::<Cluster>() * CLUSTER_SIZE);
+    }
+
+    #[test]
+    fn old_shallow_entries_go_first() {
+        let mut tt = TranspositionTable::new(1);
+        let keys = (0..6).map(|i| Key(0x7777_0000_0000_0000 | i)).collect::<Vec<_>>();
+        let m = Move::new(D2, D4);
+
+        for (i, &key) in keys[..CLUSTER_SIZE].iter().enumerate() {
+            tt.store(key, m, 0, [12, 12, 12, 2][i], Bound::Exact);
+        }
+
+        tt.new_search();
+        tt.store(keys[4], m, 0, 3, Bound::Exact);
+        assert!(tt.probe(keys[3]).is_none());
+        assert!(tt.probe(keys[4]).is_some());
+
+        // The old deep entries outlast a fresh shallow one
+        tt.store(keys[5], m, 0, 3, Bound::Exact);
+        assert!(tt.probe(keys[4]).is_none());
+        assert!(keys[..3].iter().all(|&k| tt.probe(k).is_some()));
+    }
+}